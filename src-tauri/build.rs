@@ -1,3 +1,29 @@
+use std::process::Command;
+
 fn main() {
+    embed_git_commit();
     tauri_build::build()
 }
+
+/// Exposes the current commit as `VISION_GIT_COMMIT` for `get_app_info`. CI can set the variable
+/// itself; outside a git checkout it is simply left unset.
+fn embed_git_commit() {
+    println!("cargo:rerun-if-env-changed=VISION_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+
+    if std::env::var("VISION_GIT_COMMIT").is_ok() {
+        return;
+    }
+
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=VISION_GIT_COMMIT={}", commit.trim());
+    }
+}
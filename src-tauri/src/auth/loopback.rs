@@ -0,0 +1,167 @@
+//! One-shot HTTP listener for RFC 8252 loopback redirects.
+
+use std::net::{Ipv4Addr, TcpListener as StdTcpListener};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+
+use super::{AuthError, PENDING_TTL};
+
+const CALLBACK_PATH: &str = "/callback";
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+/// Browsers open preconnect sockets that may never send a request; those are dropped after this.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const SUCCESS_PAGE: &str = "<!doctype html><html><body><h1>Login complete</h1>\
+<p>You may close this window and return to Vision.</p></body></html>";
+const FAILURE_PAGE: &str = "<!doctype html><html><body><h1>Login failed</h1>\
+<p>You may close this window and try again from Vision.</p></body></html>";
+
+/// Binds an ephemeral port on 127.0.0.1 and returns the listener with its redirect URI.
+pub(super) fn bind() -> Result<(StdTcpListener, String), AuthError> {
+    let listener = StdTcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(loopback_error)?;
+    let port = listener.local_addr().map_err(loopback_error)?.port();
+    Ok((listener, format!("http://127.0.0.1:{port}{CALLBACK_PATH}")))
+}
+
+/// Waits for the browser to hit the callback path and returns the full callback URL together
+/// with the connection, so the caller can answer once the login has been processed.
+/// Requests for other paths (favicons and the like) get a 404. Each connection is read on its
+/// own task, so an idle socket can't hold up the callback behind it. Gives up when `cancelled`
+/// fires or its sender is dropped.
+pub(super) async fn accept_callback(
+    listener: StdTcpListener,
+    mut cancelled: oneshot::Receiver<()>,
+) -> Result<(Url, TcpStream), AuthError> {
+    listener.set_nonblocking(true).map_err(loopback_error)?;
+    let port = listener.local_addr().map_err(loopback_error)?.port();
+    let listener = TcpListener::from_std(listener).map_err(loopback_error)?;
+
+    let (callbacks, mut callback) = mpsc::channel(1);
+    let expired = tokio::time::sleep(PENDING_TTL);
+    tokio::pin!(expired);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted.map_err(loopback_error)?;
+                let callbacks = callbacks.clone();
+                tokio::spawn(async move {
+                    if let Some(found) = read_callback(stream, port).await {
+                        let _ = callbacks.send(found).await;
+                    }
+                });
+            }
+            Some(found) = callback.recv() => return found,
+            _ = &mut cancelled => return Err(AuthError::LoginCancelled),
+            _ = &mut expired => return Err(AuthError::PendingExpired),
+        }
+    }
+}
+
+/// Returns the callback URL with its connection, or answers any other request itself.
+async fn read_callback(
+    mut stream: TcpStream,
+    port: u16,
+) -> Option<Result<(Url, TcpStream), AuthError>> {
+    let target = tokio::time::timeout(READ_TIMEOUT, read_request_target(&mut stream))
+        .await
+        .ok()?;
+    let Some(target) = target else {
+        respond(stream, "400 Bad Request", "").await;
+        return None;
+    };
+    if target.split('?').next() != Some(CALLBACK_PATH) {
+        respond(stream, "404 Not Found", "").await;
+        return None;
+    }
+    let url = Url::parse(&format!("http://127.0.0.1:{port}{target}"))
+        .map_err(|_| AuthError::InvalidRedirectUrl);
+    Some(url.map(|url| (url, stream)))
+}
+
+pub(super) async fn finish(stream: TcpStream, success: bool) {
+    let page = if success { SUCCESS_PAGE } else { FAILURE_PAGE };
+    respond(stream, "200 OK", page).await;
+}
+
+/// Reads the request head and returns the target of a `GET` request line.
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() >= MAX_REQUEST_BYTES {
+            return None;
+        }
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let mut parts = head.lines().next()?.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    parts.next().map(str::to_string)
+}
+
+async fn respond(mut stream: TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\n\
+Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn loopback_error(err: std::io::Error) -> AuthError {
+    AuthError::Loopback(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(listener: &StdTcpListener) -> TcpStream {
+        let addr = listener.local_addr().unwrap();
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    #[test]
+    fn idle_connection_does_not_hold_up_the_callback() {
+        let (listener, _) = bind().unwrap();
+        let (_cancel, cancelled) = oneshot::channel();
+        let url = tauri::async_runtime::block_on(async {
+            // A preconnect socket that never sends anything, opened before the real request.
+            let _idle = connect(&listener).await;
+            let mut browser = connect(&listener).await;
+            browser
+                .write_all(b"GET /callback?code=abc&state=xyz HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+
+            let accept = accept_callback(listener, cancelled);
+            let (url, _) = tokio::time::timeout(Duration::from_secs(5), accept)
+                .await
+                .expect("the idle connection blocked the callback")
+                .unwrap();
+            url
+        });
+        assert_eq!(url.path(), CALLBACK_PATH);
+        assert_eq!(url.query(), Some("code=abc&state=xyz"));
+    }
+
+    #[test]
+    fn cancelling_stops_the_wait() {
+        let (listener, _) = bind().unwrap();
+        let (cancel, cancelled) = oneshot::channel();
+        cancel.send(()).unwrap();
+        let result = tauri::async_runtime::block_on(accept_callback(listener, cancelled));
+        assert!(matches!(result, Err(AuthError::LoginCancelled)));
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
@@ -8,10 +9,13 @@ use rand::{rngs::OsRng, RngCore};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use tauri::{AppHandle, Emitter, State};
+use tauri::{AppHandle, Emitter, Manager, State};
 use tauri_plugin_store::StoreExt;
 use url::Url;
 
+mod loopback;
+mod store_crypto;
+
 const PENDING_TTL: Duration = Duration::from_secs(600);
 const TOKEN_SERVICE: &str = "vision-desktop";
 const TOKEN_ACCOUNT: &str = "oauth_tokens";
@@ -19,7 +23,24 @@ const STORE_PATH: &str = "auth.json";
 const STORE_KEY: &str = "tokens";
 const STORE_PENDING_KEY: &str = "oauth_pending";
 const STORE_PROVIDER_KEY: &str = "oauth_provider";
+const STORE_ACCOUNTS_KEY: &str = "oauth_accounts";
+/// Set once `migrate_legacy_storage` has moved pre-namespace tokens, so it only runs once.
+const STORE_NAMESPACED_KEY: &str = "oauth_namespaced";
+const STORE_STORAGE_PREFERENCE_KEY: &str = "token_storage";
+const DEFAULT_ACCOUNT_ID: &str = "default";
 const REFRESH_WINDOW_SECS: i64 = 60;
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const WAKE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const WAKE_JUMP_THRESHOLD_SECS: i64 = 30;
+const EXPIRY_WARNING_SECS: i64 = 300;
+const DEVICE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const DEVICE_DEFAULT_INTERVAL_SECS: u64 = 5;
+const DEVICE_SLOW_DOWN_SECS: u64 = 5;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 10;
+const REFRESH_MAX_ATTEMPTS: u32 = 3;
+const REFRESH_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const REFRESH_RETRY_BUDGET: Duration = Duration::from_secs(20);
+const TOKEN_ERROR_BODY_MAX: usize = 1024;
 
 #[derive(Debug, thiserror::Error)]
 pub enum AuthError {
@@ -31,18 +52,58 @@ pub enum AuthError {
     MissingState,
     #[error("state mismatch")]
     StateMismatch,
+    #[error("token response has no id_token")]
+    MissingIdToken,
+    #[error("invalid id_token")]
+    InvalidIdToken,
+    #[error("id_token nonce mismatch")]
+    NonceMismatch,
     #[error("pending login expired")]
     PendingExpired,
     #[error("no pending login state; start the login from inside the app")]
     NoPendingState,
     #[error("authorization denied: {0}")]
     AuthorizationDenied(String),
-    #[error("token exchange failed with status {0}")]
-    TokenExchangeFailed(StatusCode),
+    /// `detail` is the provider's `error`/`error_description`, or the start of a non-JSON body.
+    #[error("token exchange failed with status {status}{}", detail_suffix(.detail))]
+    TokenExchangeFailed {
+        status: StatusCode,
+        detail: Option<String>,
+    },
     #[error("refresh token missing")]
     RefreshTokenMissing,
+    #[error("refresh token rejected; sign in again")]
+    RefreshTokenRejected,
     #[error("provider config missing; call oauth_prepare_login first")]
     ProviderConfigMissing,
+    #[error("provider has no device_authorization_endpoint")]
+    DeviceFlowUnsupported,
+    #[error("no pending device login; call oauth_prepare_device_login first")]
+    NoPendingDeviceLogin,
+    #[error("device code expired")]
+    DeviceCodeExpired,
+    #[error("no pending loopback login; call oauth_prepare_loopback_login first")]
+    NoPendingLoopback,
+    #[error("loopback listener error: {0}")]
+    Loopback(String),
+    #[error("login cancelled")]
+    LoginCancelled,
+    #[error("not authenticated")]
+    NotAuthenticated,
+    #[error("access token expired and cannot be refreshed; sign in again")]
+    TokenExpired,
+    #[error("provider has no userinfo_endpoint")]
+    UserInfoUnsupported,
+    #[error("userinfo request failed with status {0}")]
+    UserInfoFailed(StatusCode),
+    #[error("token revocation failed with status {0}")]
+    RevocationFailed(StatusCode),
+    #[error("provider has no introspection_endpoint")]
+    IntrospectionUnsupported,
+    #[error("token introspection failed with status {0}")]
+    IntrospectionFailed(StatusCode),
+    #[error("only available in debug builds")]
+    DebugOnly,
     #[error("storage error: {0}")]
     Storage(String),
     #[error("request error: {0}")]
@@ -51,6 +112,72 @@ pub enum AuthError {
     Serialization(String),
 }
 
+impl AuthError {
+    /// Stable identifier the frontend can match on instead of the English message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRedirectUrl => "INVALID_REDIRECT_URL",
+            Self::MissingCode => "MISSING_CODE",
+            Self::MissingState => "MISSING_STATE",
+            Self::StateMismatch => "STATE_MISMATCH",
+            Self::MissingIdToken => "MISSING_ID_TOKEN",
+            Self::InvalidIdToken => "INVALID_ID_TOKEN",
+            Self::NonceMismatch => "NONCE_MISMATCH",
+            Self::PendingExpired => "PENDING_EXPIRED",
+            Self::NoPendingState => "NO_PENDING_STATE",
+            Self::AuthorizationDenied(_) => "AUTHORIZATION_DENIED",
+            Self::TokenExchangeFailed { .. } => "TOKEN_EXCHANGE_FAILED",
+            Self::RefreshTokenMissing => "REFRESH_TOKEN_MISSING",
+            Self::RefreshTokenRejected => "REFRESH_TOKEN_REJECTED",
+            Self::ProviderConfigMissing => "PROVIDER_CONFIG_MISSING",
+            Self::DeviceFlowUnsupported => "DEVICE_FLOW_UNSUPPORTED",
+            Self::NoPendingDeviceLogin => "NO_PENDING_DEVICE_LOGIN",
+            Self::DeviceCodeExpired => "DEVICE_CODE_EXPIRED",
+            Self::NoPendingLoopback => "NO_PENDING_LOOPBACK",
+            Self::Loopback(_) => "LOOPBACK_FAILED",
+            Self::LoginCancelled => "LOGIN_CANCELLED",
+            Self::NotAuthenticated => "NOT_AUTHENTICATED",
+            Self::TokenExpired => "TOKEN_EXPIRED",
+            Self::UserInfoUnsupported => "USERINFO_UNSUPPORTED",
+            Self::UserInfoFailed(_) => "USERINFO_FAILED",
+            Self::RevocationFailed(_) => "REVOCATION_FAILED",
+            Self::IntrospectionUnsupported => "INTROSPECTION_UNSUPPORTED",
+            Self::IntrospectionFailed(_) => "INTROSPECTION_FAILED",
+            Self::DebugOnly => "DEBUG_ONLY",
+            Self::Storage(_) => "STORAGE_ERROR",
+            Self::Request(_) => "NETWORK_ERROR",
+            Self::Serialization(_) => "SERIALIZATION_ERROR",
+        }
+    }
+}
+
+fn detail_suffix(detail: &Option<String>) -> String {
+    detail
+        .as_deref()
+        .map(|detail| format!(": {detail}"))
+        .unwrap_or_default()
+}
+
+/// Error returned by the auth commands: `code` is stable, `message` is for logs and fallbacks.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthCommandError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<AuthError> for AuthCommandError {
+    fn from(err: AuthError) -> Self {
+        Self {
+            code: err.code(),
+            message: err.to_string(),
+        }
+    }
+}
+
+fn lock_error<T>(_: PoisonError<T>) -> AuthError {
+    AuthError::Storage("lock failed".into())
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ProviderConfig {
     pub client_id: String,
@@ -61,6 +188,54 @@ pub struct ProviderConfig {
     pub scopes: Vec<String>,
     pub extra_auth_params: Option<HashMap<String, String>>,
     pub extra_token_params: Option<HashMap<String, String>>,
+    /// Headers added to token endpoint requests, e.g. `X-Tenant-Id`. Replaces the default
+    /// `Content-Type` only when it is listed here.
+    pub extra_token_headers: Option<HashMap<String, String>>,
+    pub device_authorization_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub revocation_endpoint: Option<String>,
+    /// RFC 7662 endpoint used by `oauth_introspect_token`.
+    pub introspection_endpoint: Option<String>,
+    /// Client authentication for introspection when it differs from `token_auth_method`; many
+    /// providers require `client_secret_basic` there.
+    pub introspection_auth_method: Option<TokenAuthMethod>,
+    pub use_oidc: Option<bool>,
+    pub token_auth_method: Option<TokenAuthMethod>,
+    pub pkce_method: Option<PkceMethod>,
+    pub request_timeout_secs: Option<u64>,
+    /// PEM bundle trusted in addition to the system roots, e.g. a TLS-intercepting proxy's CA.
+    pub extra_ca_cert_pem: Option<String>,
+    /// Client certificate and private key for providers that require mutual TLS. Both must be
+    /// set together; the key may be PKCS#8, PKCS#1 or SEC1.
+    pub client_cert_pem: Option<String>,
+    pub client_key_pem: Option<String>,
+}
+
+/// How the client secret is sent to the token, device and revocation endpoints.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenAuthMethod {
+    #[default]
+    ClientSecretPost,
+    ClientSecretBasic,
+}
+
+/// PKCE `code_challenge_method`. `plain` only exists for providers that reject S256.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum PkceMethod {
+    #[default]
+    S256,
+    #[serde(rename = "plain")]
+    Plain,
+}
+
+impl PkceMethod {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::S256 => "S256",
+            Self::Plain => "plain",
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -71,33 +246,88 @@ pub struct PrepareLoginResponse {
     pub authorization_url: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct DeviceLoginResponse {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub expires_in: i64,
+    pub interval: u64,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct TokenSet {
     access_token: String,
     refresh_token: Option<String>,
     expires_at: i64,
+    #[serde(default)]
+    granted_scopes: Option<Vec<String>>,
+    /// Where the tokens were last read from or written to; not persisted.
+    #[serde(skip)]
+    storage_backend: Option<StorageBackend>,
 }
 
-#[derive(Debug)]
-struct PendingAuth {
-    state: String,
-    code_verifier: String,
-    provider: ProviderConfig,
-    created_at: Instant,
+/// `Store` means the OS secret store was unavailable and the tokens live, encrypted, in the app
+/// store instead.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    Keyring,
+    Store,
+}
+
+/// Where `save_tokens` may put tokens. `Auto` tries the keyring and falls back to the app store;
+/// the other two use only that backend and fail instead of falling back.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenStoragePreference {
+    #[default]
+    Auto,
+    Keyring,
+    Store,
 }
 
+/// A login waiting for its callback. The same record is kept in memory and in the store, and its
+/// age is always taken from the wall-clock `created_at_epoch`, so time spent asleep counts
+/// towards `PENDING_TTL` and a restart doesn't reset it.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct PendingAuthRecord {
+    #[serde(default = "default_account_id")]
+    account_id: String,
     state: String,
     code_verifier: String,
+    #[serde(default)]
+    nonce: Option<String>,
     provider: ProviderConfig,
     created_at_epoch: i64,
 }
 
+/// The device code never leaves the backend; the frontend only sees the user code.
+#[derive(Clone, Debug)]
+struct PendingDevice {
+    account_id: String,
+    device_code: String,
+    provider: ProviderConfig,
+    interval: u64,
+    expires_at: i64,
+}
+
 pub struct AuthState {
-    pending: Mutex<Option<PendingAuth>>,
+    pending: Mutex<Option<PendingAuthRecord>>,
     processing: Mutex<bool>,
     provider: Mutex<Option<ProviderConfig>>,
+    device: Mutex<Option<PendingDevice>>,
+    loopback: Mutex<Option<TcpListener>>,
+    /// Fired by `oauth_cancel_login` to stop an `oauth_await_loopback_callback` that already
+    /// took the listener.
+    loopback_cancel: Mutex<Option<tokio::sync::oneshot::Sender<()>>>,
+    /// Held per account for the whole load-refresh-save of a token refresh, so concurrent
+    /// refreshes of one account queue up instead of racing with a rotated refresh token, while
+    /// other accounts refresh independently.
+    refresh: RefreshLocks,
+    /// `expires_at` per account (`None` when signed out), kept in sync by `save_tokens` and
+    /// `clear_tokens` so countdowns don't read the keychain on every tick.
+    expiries: Mutex<HashMap<String, Option<i64>>>,
 }
 
 impl AuthState {
@@ -106,14 +336,141 @@ impl AuthState {
             pending: Mutex::new(None),
             processing: Mutex::new(false),
             provider: Mutex::new(None),
+            device: Mutex::new(None),
+            loopback: Mutex::new(None),
+            loopback_cancel: Mutex::new(None),
+            refresh: RefreshLocks::default(),
+            expiries: Mutex::new(HashMap::new()),
         }
     }
+
+    /// Forgets the provider and any pending login, once no account is left to use them:
+    /// `clear_persisted` drops the stored copies, and the in-memory ones go too, or
+    /// `current_provider` would keep serving the old provider from memory.
+    fn clear_shared(
+        &self,
+        clear_persisted: impl FnOnce() -> Result<(), AuthError>,
+    ) -> Result<(), AuthError> {
+        *self.provider.lock().map_err(lock_error)? = None;
+        *self.pending.lock().map_err(lock_error)? = None;
+        clear_persisted()
+    }
+}
+
+/// One lock per account, each holding when that account was last refreshed.
+#[derive(Default)]
+struct RefreshLocks {
+    accounts: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<Instant>>>>>,
+}
+
+impl RefreshLocks {
+    /// Waits for the account's lock. The request time is taken before waiting, so the guard can
+    /// tell whether a refresh finished while this caller was queued.
+    async fn lock(&self, account_id: &str) -> Result<RefreshGuard, AuthError> {
+        let requested_at = Instant::now();
+        let lock = self
+            .accounts
+            .lock()
+            .map_err(lock_error)?
+            .entry(account_id.to_string())
+            .or_default()
+            .clone();
+        Ok(RefreshGuard {
+            requested_at,
+            last_refresh: lock.lock_owned().await,
+        })
+    }
+}
+
+struct RefreshGuard {
+    requested_at: Instant,
+    last_refresh: tokio::sync::OwnedMutexGuard<Option<Instant>>,
+}
+
+impl RefreshGuard {
+    /// Whether another caller refreshed the account while this one waited, in which case its
+    /// result is reused instead of spending the refresh token again.
+    fn refreshed_meanwhile(&self) -> bool {
+        self.last_refresh
+            .is_some_and(|refreshed_at| refreshed_at >= self.requested_at)
+    }
+
+    fn mark_refreshed(&mut self) {
+        *self.last_refresh = Some(Instant::now());
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FreshAccessToken {
+    pub access_token: String,
+    pub expires_at: i64,
+}
+
+/// Redacted snapshot for support; never contains tokens or the client secret.
+#[derive(Debug, Serialize)]
+pub struct AuthDebugState {
+    pub accounts: Vec<AccountDebugState>,
+    pub provider_client_id: Option<String>,
+    pub provider_token_endpoint: Option<String>,
+    pub pending_login: bool,
+    pub pending_device_login: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountDebugState {
+    pub account_id: String,
+    pub expires_at: i64,
+    pub seconds_remaining: i64,
+    pub granted_scopes: Option<Vec<String>>,
+    pub has_refresh_token: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct TokenExpiring {
+    account_id: String,
+    seconds_remaining: i64,
 }
 
 #[derive(Clone, Serialize)]
 pub struct AuthStatus {
+    pub account_id: String,
     pub is_authenticated: bool,
     pub expires_at: Option<i64>,
+    pub granted_scopes: Option<Vec<String>>,
+    pub storage_backend: Option<StorageBackend>,
+}
+
+impl AuthStatus {
+    fn signed_in(account_id: &str, tokens: &TokenSet) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            is_authenticated: true,
+            expires_at: Some(tokens.expires_at),
+            granted_scopes: tokens.granted_scopes.clone(),
+            storage_backend: tokens.storage_backend,
+        }
+    }
+
+    fn signed_out(account_id: &str) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            is_authenticated: false,
+            expires_at: None,
+            granted_scopes: None,
+            storage_backend: None,
+        }
+    }
+}
+
+/// Stores the provider config without starting a login, e.g. from remote config at startup, so
+/// refreshes work on a cold start before any interactive login.
+#[tauri::command]
+pub fn oauth_set_provider(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    provider: ProviderConfig,
+) -> Result<(), AuthCommandError> {
+    activate_provider(&app, &state, &provider).map_err(Into::into)
 }
 
 #[tauri::command]
@@ -121,38 +478,104 @@ pub fn oauth_prepare_login(
     app: AppHandle,
     state: State<'_, AuthState>,
     provider: ProviderConfig,
-) -> Result<PrepareLoginResponse, String> {
+    account_id: Option<String>,
+    redirect_uri_override: Option<String>,
+) -> Result<PrepareLoginResponse, AuthCommandError> {
+    prepare_login(&app, &state, provider, account_id, redirect_uri_override).map_err(Into::into)
+}
+
+/// Same as `oauth_prepare_login`, but returns only the authorization url. The state, PKCE
+/// verifier and nonce stay in the backend, which is all the callback handling needs.
+#[tauri::command]
+pub fn oauth_prepare_login_url(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    provider: ProviderConfig,
+    account_id: Option<String>,
+) -> Result<String, AuthCommandError> {
+    let response = prepare_login(&app, &state, provider, account_id, None)?;
+    Ok(response.authorization_url)
+}
+
+/// Starts a login that redirects to a temporary `http://127.0.0.1:<port>` listener instead of
+/// the `vision://` deep link. Follow up with `oauth_await_loopback_callback`.
+#[tauri::command]
+pub fn oauth_prepare_loopback_login(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    provider: ProviderConfig,
+    account_id: Option<String>,
+) -> Result<PrepareLoginResponse, AuthCommandError> {
+    let (listener, redirect_uri) = loopback::bind()?;
+    let response = prepare_login(&app, &state, provider, account_id, Some(redirect_uri))?;
+
+    let mut loopback = state.loopback.lock().map_err(lock_error)?;
+    *loopback = Some(listener);
+    Ok(response)
+}
+
+/// Waits for the browser to reach the loopback listener and completes the login with the
+/// received code. Times out together with the pending login.
+#[tauri::command]
+pub async fn oauth_await_loopback_callback(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+) -> Result<(), AuthCommandError> {
+    let listener = state
+        .loopback
+        .lock()
+        .map_err(lock_error)?
+        .take()
+        .ok_or(AuthError::NoPendingLoopback)?;
+    let (cancel, cancelled) = tokio::sync::oneshot::channel();
+    *state.loopback_cancel.lock().map_err(lock_error)? = Some(cancel);
+
+    let (url, stream) = loopback::accept_callback(listener, cancelled).await?;
+    let result = handle_callback_url(&app, &state, url).await;
+    loopback::finish(stream, result.is_ok()).await;
+    result.map_err(Into::into)
+}
+
+fn prepare_login(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    mut provider: ProviderConfig,
+    account_id: Option<String>,
+    redirect_uri_override: Option<String>,
+) -> Result<PrepareLoginResponse, AuthError> {
+    // The pending record keeps this provider copy, so the token exchange sends the same
+    // redirect_uri as the authorization request.
+    if let Some(redirect_uri) = redirect_uri_override {
+        provider.redirect_uri = redirect_uri;
+    }
+
+    let account_id = account_or_default(account_id);
     let state_value = random_urlsafe(32);
     let code_verifier = random_urlsafe(64);
-    let code_challenge = pkce_challenge(&code_verifier);
-
-    let mut pending = state.pending.lock().map_err(|_| "lock failed")?;
-    *pending = Some(PendingAuth {
+    let code_challenge = pkce_challenge(&code_verifier, provider.pkce_method.unwrap_or_default());
+    let nonce = provider
+        .use_oidc
+        .unwrap_or(false)
+        .then(|| random_urlsafe(32));
+
+    let record = PendingAuthRecord {
+        account_id,
         state: state_value.clone(),
         code_verifier: code_verifier.clone(),
+        nonce: nonce.clone(),
         provider: provider.clone(),
-        created_at: Instant::now(),
-    });
+        created_at_epoch: now_epoch(),
+    };
+    let mut pending = state.pending.lock().map_err(lock_error)?;
+    *pending = Some(record.clone());
     drop(pending);
 
-    let mut provider_state = state.provider.lock().map_err(|_| "lock failed")?;
-    *provider_state = Some(provider.clone());
-    drop(provider_state);
-
-    persist_provider(&app, &provider).map_err(|err| err.to_string())?;
-    persist_pending(
-        &app,
-        &PendingAuthRecord {
-            state: state_value.clone(),
-            code_verifier: code_verifier.clone(),
-            provider: provider.clone(),
-            created_at_epoch: now_epoch(),
-        },
-    )
-    .map_err(|err| err.to_string())?;
+    // The provider only becomes current once the login succeeds; switching now would hide the
+    // signed-in accounts of the previous provider, even if this login is cancelled.
+    persist_pending(app, &record)?;
 
-    let authorization_url = build_authorization_url(&provider, &state_value, &code_challenge)
-        .map_err(|err| err.to_string())?;
+    let authorization_url =
+        build_authorization_url(&provider, &state_value, &code_challenge, nonce.as_deref())?;
 
     Ok(PrepareLoginResponse {
         state: state_value,
@@ -162,393 +585,1821 @@ pub fn oauth_prepare_login(
     })
 }
 
+#[tauri::command]
+pub async fn oauth_prepare_device_login(
+    state: State<'_, AuthState>,
+    provider: ProviderConfig,
+    account_id: Option<String>,
+) -> Result<DeviceLoginResponse, AuthCommandError> {
+    let authorization = request_device_authorization(&provider).await?;
+    let interval = authorization
+        .interval
+        .unwrap_or(DEVICE_DEFAULT_INTERVAL_SECS);
+
+    let mut device = state.device.lock().map_err(lock_error)?;
+    *device = Some(PendingDevice {
+        account_id: account_or_default(account_id),
+        device_code: authorization.device_code,
+        provider,
+        interval,
+        expires_at: now_epoch() + authorization.expires_in,
+    });
+    drop(device);
+
+    Ok(DeviceLoginResponse {
+        user_code: authorization.user_code,
+        verification_uri: authorization.verification_uri,
+        verification_uri_complete: authorization.verification_uri_complete,
+        expires_in: authorization.expires_in,
+        interval,
+    })
+}
+
+/// Polls the token endpoint at the provider's interval until the user approves the device,
+/// denies it, or the device code expires. Starting another device login stops the poll.
+#[tauri::command]
+pub async fn oauth_poll_device_token(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+) -> Result<AuthStatus, AuthCommandError> {
+    let result = poll_device_token(&state).await;
+
+    // Network errors leave the device login in place so polling can be resumed.
+    if matches!(
+        result,
+        Ok(_) | Err(AuthError::DeviceCodeExpired | AuthError::AuthorizationDenied(_))
+    ) {
+        if let Ok(mut device) = state.device.lock() {
+            *device = None;
+        }
+    }
+
+    let (device, mut token_set) = result?;
+    let account_id = device.account_id;
+    activate_provider(&app, &state, &device.provider)?;
+    save_tokens(&app, &account_id, &mut token_set)?;
+    emit_auth_changed(&app, &account_id, &token_set);
+
+    Ok(AuthStatus::signed_in(&account_id, &token_set))
+}
+
+/// Starts a session from a refresh token carried over from an earlier app version, without an
+/// interactive login. The token is redeemed right away; if the provider rejects it nothing is
+/// stored and the call fails with `REFRESH_TOKEN_REJECTED`.
+#[tauri::command]
+pub async fn oauth_import_refresh_token(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    provider: ProviderConfig,
+    refresh_token: String,
+    account_id: Option<String>,
+) -> Result<AuthStatus, AuthCommandError> {
+    let account_id = account_or_default(account_id);
+    let refresh_token = refresh_token.trim();
+    if refresh_token.is_empty() {
+        return Err(AuthError::RefreshTokenMissing.into());
+    }
+
+    let mut refresh_guard = state.refresh.lock(&account_id).await?;
+    let mut token_set = refresh_tokens(&provider, refresh_token).await?;
+
+    activate_provider(&app, &state, &provider)?;
+    save_tokens(&app, &account_id, &mut token_set)?;
+    refresh_guard.mark_refreshed();
+    drop(refresh_guard);
+
+    emit_auth_changed(&app, &account_id, &token_set);
+    Ok(AuthStatus::signed_in(&account_id, &token_set))
+}
+
 #[tauri::command]
 pub async fn oauth_handle_callback(
     app: AppHandle,
     state: State<'_, AuthState>,
     url: String,
-) -> Result<(), String> {
-    let url = Url::parse(&url).map_err(|_| AuthError::InvalidRedirectUrl.to_string())?;
+) -> Result<(), AuthCommandError> {
+    let url = Url::parse(&url).map_err(|_| AuthError::InvalidRedirectUrl)?;
     handle_callback_url(&app, &state, url)
         .await
-        .map_err(|err| err.to_string())
+        .map_err(Into::into)
 }
 
+/// Drops any pending browser login, so a late callback for it is rejected, and stops a running
+/// `oauth_await_loopback_callback`. Succeeds even if nothing was pending.
 #[tauri::command]
-pub async fn oauth_refresh_if_needed(
+pub fn oauth_cancel_login(
     app: AppHandle,
     state: State<'_, AuthState>,
-) -> Result<AuthStatus, String> {
-    let provider = {
-        let in_memory = state
-            .provider
-            .lock()
-            .map_err(|_| "lock failed")?
-            .clone();
-        if let Some(provider) = in_memory {
-            provider
-        } else {
-            let stored = load_provider(&app)
-                .map_err(|err| err.to_string())?
-                .ok_or(AuthError::ProviderConfigMissing)
-                .map_err(|err| err.to_string())?;
-            let mut provider_state = state.provider.lock().map_err(|_| "lock failed")?;
-            *provider_state = Some(stored.clone());
-            stored
+) -> Result<(), AuthCommandError> {
+    if let Ok(mut loopback) = state.loopback.lock() {
+        *loopback = None;
+    }
+    if let Ok(mut cancel) = state.loopback_cancel.lock() {
+        if let Some(cancel) = cancel.take() {
+            let _ = cancel.send(());
         }
-    };
+    }
+    clear_pending(&state, &app).map_err(Into::into)
+}
 
-    let Some(tokens) = load_tokens(&app).map_err(|err| err.to_string())? else {
-        return Ok(AuthStatus {
-            is_authenticated: false,
-            expires_at: None,
-        });
+#[tauri::command]
+pub async fn oauth_refresh_if_needed(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<AuthStatus, AuthCommandError> {
+    refresh_if_needed(&app, &state, &account_or_default(account_id))
+        .await
+        .map_err(Into::into)
+}
+
+async fn refresh_if_needed(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    account_id: &str,
+) -> Result<AuthStatus, AuthError> {
+    refresh_if_needed_with(
+        &state.refresh,
+        account_id,
+        || current_provider(app, state),
+        || load_tokens(app, account_id),
+        |provider, refresh_token| async move {
+            refresh_account(app, &provider, account_id, &refresh_token).await
+        },
+    )
+    .await
+}
+
+/// `refresh_if_needed` with the provider lookup, storage and refresh passed in, like
+/// `fresh_tokens_with`.
+async fn refresh_if_needed_with<Provider, Load, Refresh, Refreshed>(
+    locks: &RefreshLocks,
+    account_id: &str,
+    provider: Provider,
+    load: Load,
+    refresh: Refresh,
+) -> Result<AuthStatus, AuthError>
+where
+    Provider: FnOnce() -> Result<ProviderConfig, AuthError>,
+    Load: FnOnce() -> Result<Option<TokenSet>, AuthError>,
+    Refresh: FnOnce(ProviderConfig, String) -> Refreshed,
+    Refreshed: std::future::Future<Output = Result<TokenSet, AuthError>>,
+{
+    let mut refresh_guard = locks.lock(account_id).await?;
+    let provider = provider()?;
+
+    let Some(tokens) = load()? else {
+        return Ok(AuthStatus::signed_out(account_id));
     };
 
     let now = now_epoch();
-    if tokens.expires_at - now > REFRESH_WINDOW_SECS {
-        return Ok(AuthStatus {
-            is_authenticated: true,
-            expires_at: Some(tokens.expires_at),
-        });
+    if tokens.expires_at - now > REFRESH_WINDOW_SECS || refresh_guard.refreshed_meanwhile() {
+        return Ok(AuthStatus::signed_in(account_id, &tokens));
     }
 
     let refresh_token = tokens
         .refresh_token
         .clone()
-        .ok_or(AuthError::RefreshTokenMissing)
-        .map_err(|err| err.to_string())?;
+        .ok_or(AuthError::RefreshTokenMissing)?;
 
-    let refreshed = refresh_tokens(&provider, &refresh_token)
-        .await
-        .map_err(|err| err.to_string())?;
+    let refreshed = refresh(provider, refresh_token).await?;
+    refresh_guard.mark_refreshed();
 
-    save_tokens(&app, &refreshed).map_err(|err| err.to_string())?;
-    emit_auth_changed(&app, &refreshed);
+    Ok(AuthStatus::signed_in(account_id, &refreshed))
+}
 
-    Ok(AuthStatus {
-        is_authenticated: true,
-        expires_at: Some(refreshed.expires_at),
-    })
+/// Signs out one account (the default one when `account_id` is omitted); other accounts stay
+/// signed in. `auth:changed` is emitted for that account only.
+#[tauri::command]
+pub async fn oauth_logout(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<(), AuthCommandError> {
+    let account_id = account_or_default(account_id);
+    revoke_stored_tokens(&app, &state, &account_id).await;
+    clear_tokens(&app, &account_id)?;
+    emit_signed_out(&app, &account_id);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn oauth_get_auth_state(
+    app: AppHandle,
+    account_id: Option<String>,
+) -> Result<AuthStatus, AuthCommandError> {
+    auth_status(&app, &account_or_default(account_id)).map_err(Into::into)
 }
 
+/// Seconds until the account's access token expires, without refreshing it. `None` when signed
+/// out or already expired. Served from memory after the first call per account.
 #[tauri::command]
-pub fn oauth_logout(app: AppHandle) -> Result<(), String> {
-    clear_tokens(&app).map_err(|err| err.to_string())?;
-    let status = AuthStatus {
-        is_authenticated: false,
-        expires_at: None,
+pub fn oauth_seconds_until_expiry(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<Option<i64>, AuthCommandError> {
+    let account_id = account_or_default(account_id);
+    let cached = state
+        .expiries
+        .lock()
+        .map_err(lock_error)?
+        .get(&account_id)
+        .copied();
+    let expires_at = match cached {
+        Some(expires_at) => expires_at,
+        None => {
+            let expires_at = load_tokens(&app, &account_id)?.map(|tokens| tokens.expires_at);
+            cache_expiry(&app, &account_id, expires_at);
+            expires_at
+        }
     };
-    let _ = app.emit("auth:changed", status);
-    Ok(())
+
+    Ok(expires_at
+        .map(|expires_at| expires_at - now_epoch())
+        .filter(|remaining| *remaining > 0))
 }
 
+/// Lists every account with stored tokens. The default account is included when it has tokens
+/// from before accounts were tracked.
 #[tauri::command]
-pub fn oauth_get_auth_state(app: AppHandle) -> Result<AuthStatus, String> {
-    let tokens = load_tokens(&app).map_err(|err| err.to_string())?;
+pub fn oauth_list_accounts(app: AppHandle) -> Result<Vec<AuthStatus>, AuthCommandError> {
+    known_account_ids(&app)?
+        .iter()
+        .map(|account_id| auth_status(&app, account_id).map_err(Into::into))
+        .collect()
+}
+
+fn known_account_ids(app: &AppHandle) -> Result<Vec<String>, AuthError> {
+    let mut account_ids = load_account_ids(app)?;
+    if !account_ids.iter().any(|id| id == DEFAULT_ACCOUNT_ID)
+        && load_tokens(app, DEFAULT_ACCOUNT_ID)?.is_some()
+    {
+        account_ids.insert(0, DEFAULT_ACCOUNT_ID.to_string());
+    }
+    Ok(account_ids)
+}
+
+fn auth_status(app: &AppHandle, account_id: &str) -> Result<AuthStatus, AuthError> {
+    let tokens = load_tokens(app, account_id)?;
     let now = now_epoch();
 
     if let Some(tokens) = tokens {
-        let is_authenticated = tokens.expires_at > now;
         Ok(AuthStatus {
-            is_authenticated,
-            expires_at: Some(tokens.expires_at),
+            is_authenticated: tokens.expires_at > now,
+            ..AuthStatus::signed_in(account_id, &tokens)
         })
     } else {
-        Ok(AuthStatus {
-            is_authenticated: false,
-            expires_at: None,
-        })
+        Ok(AuthStatus::signed_out(account_id))
     }
 }
 
+/// Returns a usable access token, refreshing it first when needed, so callers don't have to call
+/// `oauth_refresh_if_needed` beforehand. Never returns a token past `expires_at`: without a
+/// refresh token that fails with `TOKEN_EXPIRED` instead.
 #[tauri::command]
 pub async fn oauth_get_access_token(
     app: AppHandle,
     state: State<'_, AuthState>,
-) -> Result<String, String> {
-    // Fast path: valid token still fresh enough.
-    let tokens = load_tokens(&app).map_err(|err| err.to_string())?;
-    let Some(tokens) = tokens else {
-        return Err("not authenticated".to_string());
-    };
+    account_id: Option<String>,
+) -> Result<String, AuthCommandError> {
+    access_token(&app, &state, &account_or_default(account_id))
+        .await
+        .map_err(Into::into)
+}
 
-    let now = now_epoch();
-    if tokens.expires_at - now > REFRESH_WINDOW_SECS {
-        return Ok(tokens.access_token);
-    }
-
-    // Need refresh; requires provider config.
-    let provider = {
-        let in_memory = state.provider.lock().map_err(|_| "lock failed")?.clone();
-        if let Some(provider) = in_memory {
-            provider
-        } else {
-            let stored = load_provider(&app)
-                .map_err(|err| err.to_string())?
-                .ok_or(AuthError::ProviderConfigMissing)
-                .map_err(|err| err.to_string())?;
-            let mut provider_state = state.provider.lock().map_err(|_| "lock failed")?;
-            *provider_state = Some(stored.clone());
-            stored
-        }
-    };
+/// Like `oauth_get_access_token`, but also returns the expiry so callers can tell how long the
+/// token stays valid.
+#[tauri::command]
+pub async fn oauth_get_fresh_access_token(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<FreshAccessToken, AuthCommandError> {
+    let tokens = fresh_tokens(&app, &state, &account_or_default(account_id)).await?;
+    Ok(FreshAccessToken {
+        access_token: tokens.access_token,
+        expires_at: tokens.expires_at,
+    })
+}
 
-    let refresh_token = tokens
-        .refresh_token
-        .clone()
-        .ok_or(AuthError::RefreshTokenMissing)
-        .map_err(|err| err.to_string())?;
+/// Sets where tokens are kept ("auto", "keyring" or "store") and moves every account's tokens to
+/// match, so switching doesn't sign anyone out. Nothing changes if a move fails.
+#[tauri::command]
+pub fn set_token_storage_preference(
+    app: AppHandle,
+    mode: TokenStoragePreference,
+) -> Result<(), AuthCommandError> {
+    set_storage_preference(&app, mode).map_err(Into::into)
+}
 
-    let refreshed = refresh_tokens(&provider, &refresh_token)
-        .await
-        .map_err(|err| err.to_string())?;
+fn set_storage_preference(app: &AppHandle, mode: TokenStoragePreference) -> Result<(), AuthError> {
+    let previous = load_storage_preference(app)?;
+    if previous == mode {
+        return Ok(());
+    }
 
-    save_tokens(&app, &refreshed).map_err(|err| err.to_string())?;
-    emit_auth_changed(&app, &refreshed);
+    // Old copies are only removed once every account has been saved in its new place.
+    let mut moved = Vec::new();
+    for account_id in known_account_ids(app)? {
+        let Some(mut tokens) = load_tokens_with(app, &account_id, previous)? else {
+            continue;
+        };
+        let old_backend = tokens.storage_backend;
+        save_tokens_with(app, &account_id, &mut tokens, mode)?;
+        if let Some(backend) = old_backend {
+            if tokens.storage_backend != Some(backend) {
+                moved.push((account_id, backend));
+            }
+        }
+    }
 
-    Ok(refreshed.access_token)
+    persist_storage_preference(app, mode)?;
+    for (account_id, backend) in moved {
+        clear_backend(app, &account_id, backend)?;
+    }
+    Ok(())
 }
 
-pub async fn handle_callback_url(
+/// Debug builds only, like the log plugin: summarizes the stored auth state for bug reports.
+#[tauri::command]
+pub fn auth_export_debug_state(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+) -> Result<AuthDebugState, AuthCommandError> {
+    if !cfg!(debug_assertions) {
+        return Err(AuthError::DebugOnly.into());
+    }
+
+    debug_state(&app, &state).map_err(Into::into)
+}
+
+/// Redacted auth summary shared by `auth_export_debug_state` and the diagnostics bundle.
+pub fn debug_state(
     app: &AppHandle,
     state: &State<'_, AuthState>,
-    url: Url,
-) -> Result<(), AuthError> {
-    let guard = ProcessingGuard::lock(&state.processing)?;
+) -> Result<AuthDebugState, AuthError> {
+    let now = now_epoch();
+    let mut accounts = Vec::new();
+    for account_id in known_account_ids(app)? {
+        let Some(tokens) = load_tokens(app, &account_id)? else {
+            continue;
+        };
+        accounts.push(AccountDebugState {
+            account_id,
+            expires_at: tokens.expires_at,
+            seconds_remaining: tokens.expires_at - now,
+            granted_scopes: tokens.granted_scopes,
+            has_refresh_token: tokens.refresh_token.is_some(),
+        });
+    }
 
-    let callback_error = extract_query(&url, "error");
-    let callback_error_description = extract_query(&url, "error_description");
-    let returned_state = extract_query(&url, "state");
-    let code = extract_query(&url, "code");
+    let provider = current_provider(app, state).ok();
+    let pending_login = state.pending.lock().map_err(lock_error)?.is_some()
+        || load_pending_store(app)?.is_some();
+    let pending_device_login = state.device.lock().map_err(lock_error)?.is_some();
+
+    Ok(AuthDebugState {
+        accounts,
+        provider_client_id: provider.as_ref().map(|provider| provider.client_id.clone()),
+        provider_token_endpoint: provider.map(|provider| provider.token_endpoint),
+        pending_login,
+        pending_device_login,
+    })
+}
 
-    if let Some(error_code) = callback_error {
-        clear_pending(state, app)?;
-        if returned_state.is_none() {
-            return Err(AuthError::MissingState);
-        }
-        let description = callback_error_description
-            .map(|value| format!("{error_code}: {value}"))
-            .unwrap_or(error_code)
-            .replace('+', " ");
-        return Err(AuthError::AuthorizationDenied(description));
-    }
+/// Fetches the provider's userinfo endpoint with the current access token and returns the raw
+/// JSON body.
+#[tauri::command]
+pub async fn oauth_get_user_info(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<String, AuthCommandError> {
+    let provider = current_provider(&app, &state)?;
+    let endpoint = provider
+        .userinfo_endpoint
+        .clone()
+        .ok_or(AuthError::UserInfoUnsupported)?;
+    let access_token = access_token(&app, &state, &account_or_default(account_id)).await?;
 
-    let code = code.ok_or(AuthError::MissingCode)?;
-    let returned_state = returned_state.ok_or(AuthError::MissingState)?;
+    fetch_user_info(&provider, &endpoint, &access_token)
+        .await
+        .map_err(Into::into)
+}
 
-    let pending = load_pending(state, app)?;
+/// Asks the provider's introspection endpoint (RFC 7662) whether the stored access token is still
+/// active and returns its JSON answer (`active`, `scope`, `exp`, ...). The token is sent as
+/// stored, without refreshing, since the point is to diagnose the token the app is using.
+#[tauri::command]
+pub async fn oauth_introspect_token(
+    app: AppHandle,
+    state: State<'_, AuthState>,
+    account_id: Option<String>,
+) -> Result<serde_json::Value, AuthCommandError> {
+    let mut provider = current_provider(&app, &state)?;
+    let endpoint = provider
+        .introspection_endpoint
+        .clone()
+        .ok_or(AuthError::IntrospectionUnsupported)?;
+    let tokens =
+        load_tokens(&app, &account_or_default(account_id))?.ok_or(AuthError::NotAuthenticated)?;
+    if let Some(method) = provider.introspection_auth_method {
+        provider.token_auth_method = Some(method);
+    }
+
+    introspect_token(&provider, &endpoint, &tokens.access_token)
+        .await
+        .map_err(Into::into)
+}
+
+/// The default account's access token for `http_request` calls flagged `authenticated`,
+/// refreshed first when needed.
+pub async fn bearer_token(app: &AppHandle) -> Result<String, AuthError> {
+    let state = app.state::<AuthState>();
+    access_token(app, &state, DEFAULT_ACCOUNT_ID).await
+}
+
+/// Returns the stored access token, refreshing it first when it is within
+/// `REFRESH_WINDOW_SECS` of expiry.
+async fn access_token(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    account_id: &str,
+) -> Result<String, AuthError> {
+    Ok(fresh_tokens(app, state, account_id).await?.access_token)
+}
+
+/// Loads the account's tokens, refreshing them when they are within `REFRESH_WINDOW_SECS` of
+/// expiry. Callers for the same account are serialized, so a burst of requests (e.g. after
+/// waking from sleep) sees one refresh and then reuses its result instead of racing on the same
+/// refresh token. A refresh that fails for a reason other than a rejected refresh token leaves
+/// the stored token in use for as long as it is still valid.
+async fn fresh_tokens(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    account_id: &str,
+) -> Result<TokenSet, AuthError> {
+    fresh_tokens_with(
+        &state.refresh,
+        account_id,
+        || load_tokens(app, account_id),
+        |refresh_token| async move {
+            // Need refresh; requires provider config.
+            let provider = current_provider(app, state)?;
+            refresh_account(app, &provider, account_id, &refresh_token).await
+        },
+    )
+    .await
+}
+
+/// `fresh_tokens` with storage and the refresh passed in: `load` reads the account's tokens and
+/// `refresh` redeems a refresh token and stores the result. Both run under the account's
+/// refresh lock.
+async fn fresh_tokens_with<Load, Refresh, Refreshed>(
+    locks: &RefreshLocks,
+    account_id: &str,
+    load: Load,
+    refresh: Refresh,
+) -> Result<TokenSet, AuthError>
+where
+    Load: FnOnce() -> Result<Option<TokenSet>, AuthError>,
+    Refresh: FnOnce(String) -> Refreshed,
+    Refreshed: std::future::Future<Output = Result<TokenSet, AuthError>>,
+{
+    let mut refresh_guard = locks.lock(account_id).await?;
+
+    // Fast path: valid token still fresh enough, or another caller refreshed it while we waited.
+    let Some(tokens) = load()? else {
+        return Err(AuthError::NotAuthenticated);
+    };
+
+    if refresh_guard.refreshed_meanwhile() {
+        return Ok(tokens);
+    }
+    let freshness = token_freshness(
+        tokens.expires_at,
+        now_epoch(),
+        tokens.refresh_token.is_some(),
+    );
+    let refresh_token = match (freshness, tokens.refresh_token.clone()) {
+        (TokenFreshness::Refresh, Some(refresh_token)) => refresh_token,
+        (TokenFreshness::Expired, _) => return Err(AuthError::TokenExpired),
+        _ => return Ok(tokens),
+    };
+
+    match refresh(refresh_token).await {
+        Ok(refreshed) => {
+            refresh_guard.mark_refreshed();
+            Ok(refreshed)
+        }
+        Err(err @ AuthError::RefreshTokenRejected) => Err(err),
+        Err(err) if tokens.expires_at <= now_epoch() => Err(err),
+        Err(err) => {
+            log::warn!("Token refresh failed, using the current token until it expires: {err}");
+            Ok(tokens)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TokenFreshness {
+    Usable,
+    Refresh,
+    Expired,
+}
+
+/// Tokens within `REFRESH_WINDOW_SECS` of `expires_at` are refreshed when possible. Without a
+/// refresh token the stored one is all there is, so it is handed out until it expires.
+fn token_freshness(expires_at: i64, now: i64, has_refresh_token: bool) -> TokenFreshness {
+    if expires_at - now > REFRESH_WINDOW_SECS {
+        TokenFreshness::Usable
+    } else if has_refresh_token {
+        TokenFreshness::Refresh
+    } else if expires_at > now {
+        TokenFreshness::Usable
+    } else {
+        TokenFreshness::Expired
+    }
+}
+
+/// Refreshes and stores the account's tokens. If the provider rejects the refresh token the
+/// session can never recover, so it is cleared and the UI is sent back to the login screen.
+async fn refresh_account(
+    app: &AppHandle,
+    provider: &ProviderConfig,
+    account_id: &str,
+    refresh_token: &str,
+) -> Result<TokenSet, AuthError> {
+    match refresh_tokens(provider, refresh_token).await {
+        Ok(mut refreshed) => {
+            save_tokens(app, account_id, &mut refreshed)?;
+            emit_auth_changed(app, account_id, &refreshed);
+            Ok(refreshed)
+        }
+        Err(AuthError::RefreshTokenRejected) => {
+            clear_tokens(app, account_id)?;
+            emit_signed_out(app, account_id);
+            Err(AuthError::RefreshTokenRejected)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The provider from this session, or the one persisted by an earlier session.
+fn current_provider(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+) -> Result<ProviderConfig, AuthError> {
+    current_provider_with(state, || load_provider(app))
+}
+
+/// `current_provider` with the persisted provider read by `load`.
+fn current_provider_with(
+    state: &AuthState,
+    load: impl FnOnce() -> Result<Option<ProviderConfig>, AuthError>,
+) -> Result<ProviderConfig, AuthError> {
+    let lock_failed = |_| AuthError::Storage("provider lock failed".into());
+
+    let in_memory = state.provider.lock().map_err(lock_failed)?.clone();
+    if let Some(provider) = in_memory {
+        return Ok(provider);
+    }
+
+    let stored = load()?.ok_or(AuthError::ProviderConfigMissing)?;
+    let mut provider_state = state.provider.lock().map_err(lock_failed)?;
+    *provider_state = Some(stored.clone());
+    Ok(stored)
+}
+
+/// Makes `provider` current and persists it, then adopts anything stored before storage was
+/// namespaced. Called once a login succeeded, never when one starts. A failed migration is
+/// retried next time and never blocks the login.
+fn activate_provider(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    provider: &ProviderConfig,
+) -> Result<(), AuthError> {
+    set_current_provider(state, provider)?;
+    persist_provider(app, provider)?;
+    if let Err(err) = migrate_legacy_storage(app) {
+        log::warn!("Could not move stored tokens into the provider namespace: {err}");
+    }
+    Ok(())
+}
+
+/// Makes `provider` the active one. Tokens are namespaced per provider, so cached expiries of a
+/// different provider no longer apply.
+fn set_current_provider(
+    state: &State<'_, AuthState>,
+    provider: &ProviderConfig,
+) -> Result<(), AuthError> {
+    let mut current = state.provider.lock().map_err(lock_error)?;
+    let namespace = provider_namespace(provider);
+    if current.as_ref().map(provider_namespace) != Some(namespace) {
+        state.expiries.lock().map_err(lock_error)?.clear();
+    }
+    *current = Some(provider.clone());
+    Ok(())
+}
+
+/// Background task started from `setup`. Every `EXPIRY_CHECK_INTERVAL` it emits `auth:expiring`
+/// once per token when a session is within `EXPIRY_WARNING_SECS` of expiry, and refreshes
+/// sessions that have a refresh token once they enter `REFRESH_WINDOW_SECS`, so an idle app
+/// doesn't quietly lose its session. Refresh failures are reported as `auth:error`.
+///
+/// Tokio's timers run on the monotonic clock, which stops while the machine sleeps, so a single
+/// long sleep could fire up to a full interval after resume with the token long expired. The
+/// task instead polls the wall clock every `WAKE_POLL_INTERVAL` and checks right away when it
+/// jumped by more than `WAKE_JUMP_THRESHOLD_SECS` between polls.
+pub fn spawn_expiry_watcher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut warned: HashMap<String, i64> = HashMap::new();
+        let mut last_poll = now_epoch();
+        let mut last_check = last_poll;
+        loop {
+            tokio::time::sleep(WAKE_POLL_INTERVAL).await;
+            let now = now_epoch();
+            let tick = watcher_tick(last_poll, last_check, now);
+            last_poll = now;
+
+            match tick {
+                WatcherTick::Idle => continue,
+                WatcherTick::Check => {}
+                WatcherTick::Resumed { jump } => {
+                    log::info!("Clock jumped {jump}s, likely a resume from sleep; checking tokens");
+                }
+            }
+            last_check = now;
+            check_expiry(&app, &mut warned).await;
+        }
+    });
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum WatcherTick {
+    Idle,
+    Check,
+    /// The wall clock ran `jump` seconds ahead of the poll interval, so the machine was asleep.
+    Resumed {
+        jump: i64,
+    },
+}
+
+/// What the expiry watcher does on a poll, from the wall-clock times (epoch seconds) of the
+/// previous poll, the last check and now. A resume checks right away whatever the check interval
+/// says, so a token that expired during sleep is refreshed on the first poll after wake.
+fn watcher_tick(last_poll: i64, last_check: i64, now: i64) -> WatcherTick {
+    let jump = now - last_poll - WAKE_POLL_INTERVAL.as_secs() as i64;
+    if jump > WAKE_JUMP_THRESHOLD_SECS {
+        WatcherTick::Resumed { jump }
+    } else if now - last_check >= EXPIRY_CHECK_INTERVAL.as_secs() as i64 {
+        WatcherTick::Check
+    } else {
+        WatcherTick::Idle
+    }
+}
+
+async fn check_expiry(app: &AppHandle, warned: &mut HashMap<String, i64>) {
+    let state = app.state::<AuthState>();
+    let account_ids = match known_account_ids(app) {
+        Ok(account_ids) => account_ids,
+        Err(err) => {
+            log::warn!("Expiry check could not list accounts: {err}");
+            return;
+        }
+    };
+
+    for account_id in account_ids {
+        let Ok(Some(tokens)) = load_tokens(app, &account_id) else {
+            continue;
+        };
+
+        let seconds_remaining = tokens.expires_at - now_epoch();
+
+        if seconds_remaining <= EXPIRY_WARNING_SECS
+            && warned.get(&account_id) != Some(&tokens.expires_at)
+        {
+            warned.insert(account_id.clone(), tokens.expires_at);
+            let _ = app.emit(
+                "auth:expiring",
+                TokenExpiring {
+                    account_id: account_id.clone(),
+                    seconds_remaining,
+                },
+            );
+        }
+
+        if seconds_remaining <= REFRESH_WINDOW_SECS && tokens.refresh_token.is_some() {
+            if let Err(err) = refresh_if_needed(app, &state, &account_id).await {
+                log::warn!("Automatic token refresh failed: {err}");
+                let _ = app.emit("auth:error", AuthCommandError::from(err));
+            }
+        }
+    }
+}
+
+pub async fn handle_callback_url(
+    app: &AppHandle,
+    state: &State<'_, AuthState>,
+    url: Url,
+) -> Result<(), AuthError> {
+    let guard = ProcessingGuard::lock(&state.processing)?;
+    // Lets the UI switch from "continue in your browser" to a spinner during the exchange.
+    let _ = app.emit("auth:processing", ());
+
+    let callback_error = extract_query(&url, "error");
+    let callback_error_description = extract_query(&url, "error_description");
+    let returned_state = extract_query(&url, "state");
+    let code = extract_query(&url, "code");
+
+    if let Some(error_code) = callback_error {
+        clear_pending(state, app)?;
+        if returned_state.is_none() {
+            return Err(AuthError::MissingState);
+        }
+        let description = callback_error_description
+            .map(|value| format!("{error_code}: {value}"))
+            .unwrap_or(error_code)
+            .replace('+', " ");
+        return Err(AuthError::AuthorizationDenied(description));
+    }
+
+    let code = code.ok_or(AuthError::MissingCode)?;
+    let returned_state = returned_state.ok_or(AuthError::MissingState)?;
+
+    let pending = load_pending(state, app)?;
+
+    // Only a callback to the redirect_uri this login was started with may complete it. The
+    // pending login is kept, so a stray link cannot cancel the real one.
+    if !matches_redirect_uri(&url, &pending.provider.redirect_uri) {
+        return Err(AuthError::InvalidRedirectUrl);
+    }
+
+    if let Err(err) = ensure_pending_fresh(&pending, now_epoch()) {
+        clear_pending(state, app)?;
+        return Err(err);
+    }
+
+    if pending.state != returned_state {
+        clear_pending(state, app)?;
+        return Err(AuthError::StateMismatch);
+    }
+
+    let mut token_set = exchange_code_for_token(
+        &pending.provider,
+        &code,
+        &pending.code_verifier,
+        pending.nonce.as_deref(),
+    )
+    .await?;
+    activate_provider(app, state, &pending.provider)?;
+    save_tokens(app, &pending.account_id, &mut token_set)?;
+    clear_pending(state, app)?;
+    emit_auth_changed(app, &pending.account_id, &token_set);
+    let _ = app.emit(
+        "auth:success",
+        AuthStatus::signed_in(&pending.account_id, &token_set),
+    );
+
+    drop(guard);
+    Ok(())
+}
+
+/// A login may be completed up to and including `PENDING_TTL` after it was started.
+fn ensure_pending_fresh(pending: &PendingAuthRecord, now: i64) -> Result<(), AuthError> {
+    if now - pending.created_at_epoch > PENDING_TTL.as_secs() as i64 {
+        return Err(AuthError::PendingExpired);
+    }
+    Ok(())
+}
+
+fn matches_redirect_uri(url: &Url, redirect_uri: &str) -> bool {
+    let Ok(expected) = Url::parse(redirect_uri) else {
+        return false;
+    };
+    url.scheme() == expected.scheme()
+        && url.host_str() == expected.host_str()
+        && url.port_or_known_default() == expected.port_or_known_default()
+        && url.path().trim_end_matches('/') == expected.path().trim_end_matches('/')
+}
+
+fn emit_auth_changed(app: &AppHandle, account_id: &str, tokens: &TokenSet) {
+    let _ = app.emit("auth:changed", AuthStatus::signed_in(account_id, tokens));
+}
+
+fn emit_signed_out(app: &AppHandle, account_id: &str) {
+    let _ = app.emit("auth:changed", AuthStatus::signed_out(account_id));
+}
+
+fn build_authorization_url(
+    provider: &ProviderConfig,
+    state_value: &str,
+    code_challenge: &str,
+    nonce: Option<&str>,
+) -> Result<String, AuthError> {
+    let mut url =
+        Url::parse(&provider.authorization_endpoint).map_err(|_| AuthError::InvalidRedirectUrl)?;
+
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &provider.client_id)
+        .append_pair("redirect_uri", &provider.redirect_uri)
+        .append_pair("state", state_value)
+        .append_pair("code_challenge", code_challenge)
+        .append_pair(
+            "code_challenge_method",
+            provider.pkce_method.unwrap_or_default().as_str(),
+        );
+
+    if !provider.scopes.is_empty() {
+        url.query_pairs_mut()
+            .append_pair("scope", &provider.scopes.join(" "));
+    }
+
+    if let Some(nonce) = nonce {
+        url.query_pairs_mut().append_pair("nonce", nonce);
+    }
+
+    if let Some(extra) = &provider.extra_auth_params {
+        for (key, value) in extra {
+            url.query_pairs_mut().append_pair(key, value);
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+    id_token: Option<String>,
+    scope: Option<String>,
+}
+
+/// Scopes from the token response, or the requested ones when the provider omits `scope`
+/// (RFC 6749 5.1: omitted means identical to the request).
+fn granted_scopes(scope: Option<&str>, provider: &ProviderConfig) -> Option<Vec<String>> {
+    match scope {
+        Some(scope) => Some(scope.split_whitespace().map(str::to_string).collect()),
+        None if provider.scopes.is_empty() => None,
+        None => Some(provider.scopes.clone()),
+    }
+}
+
+/// Client for requests to the provider, honoring its timeout, extra CA and mTLS settings.
+fn auth_client(provider: &ProviderConfig) -> Result<reqwest::Client, AuthError> {
+    let timeout = provider
+        .request_timeout_secs
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+    let mut builder =
+        crate::proxy::apply(reqwest::Client::builder().timeout(Duration::from_secs(timeout)))
+            .map_err(AuthError::Request)?;
+
+    if let Some(pem) = &provider.extra_ca_cert_pem {
+        let certificates = reqwest::Certificate::from_pem_bundle(pem.as_bytes())
+            .map_err(|err| AuthError::Request(format!("invalid extra_ca_cert_pem: {err}")))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&provider.client_cert_pem, &provider.client_key_pem) {
+        (Some(cert), Some(key)) => {
+            let pem = format!("{cert}\n{key}");
+            let identity = reqwest::Identity::from_pem(pem.as_bytes())
+                .map_err(|err| AuthError::Request(format!("invalid client certificate: {err}")))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(AuthError::Request(
+                "client_cert_pem and client_key_pem must be set together".into(),
+            ))
+        }
+    }
+
+    builder
+        .build()
+        .map_err(|err| AuthError::Request(err.to_string()))
+}
+
+/// Posts `form` to `endpoint`, authenticating the client the way the provider expects.
+fn client_form_request(
+    client: &reqwest::Client,
+    endpoint: &str,
+    provider: &ProviderConfig,
+    mut form: Vec<(String, String)>,
+) -> reqwest::RequestBuilder {
+    let request = client
+        .post(endpoint)
+        .header("Content-Type", "application/x-www-form-urlencoded");
+
+    let Some(secret) = &provider.client_secret else {
+        return request.form(&form);
+    };
+
+    match provider.token_auth_method.unwrap_or_default() {
+        TokenAuthMethod::ClientSecretPost => {
+            form.push(("client_secret".to_string(), secret.clone()));
+            request.form(&form)
+        }
+        TokenAuthMethod::ClientSecretBasic => {
+            // RFC 6749 2.3.1: both parts are form-urlencoded before being base64-encoded.
+            let client_id: String =
+                url::form_urlencoded::byte_serialize(provider.client_id.as_bytes()).collect();
+            let secret: String = url::form_urlencoded::byte_serialize(secret.as_bytes()).collect();
+            request.basic_auth(client_id, Some(secret)).form(&form)
+        }
+    }
+}
+
+/// `client_form_request` for the token endpoint, with the provider's `extra_token_headers`.
+fn token_endpoint_request(
+    client: &reqwest::Client,
+    provider: &ProviderConfig,
+    form: Vec<(String, String)>,
+) -> reqwest::RequestBuilder {
+    let request = client_form_request(client, &provider.token_endpoint, provider, form);
+    let Some(extra) = &provider.extra_token_headers else {
+        return request;
+    };
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in extra {
+        match (
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()),
+            reqwest::header::HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => log::warn!("Skipping invalid extra token header {name:?}"),
+        }
+    }
+    // `headers` replaces same-named headers instead of appending, so Content-Type can be
+    // overridden.
+    request.headers(headers)
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    nonce: Option<String>,
+}
+
+/// Checks the `nonce` claim of an id_token. The signature is not verified: the token comes
+/// straight from the token endpoint over TLS, which OIDC Core 3.1.3.7 accepts in its place.
+fn verify_id_token_nonce(id_token: &str, expected: &str) -> Result<(), AuthError> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or(AuthError::InvalidIdToken)?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| AuthError::InvalidIdToken)?;
+    let claims: IdTokenClaims =
+        serde_json::from_slice(&payload).map_err(|_| AuthError::InvalidIdToken)?;
+
+    if claims.nonce.as_deref() != Some(expected) {
+        return Err(AuthError::NonceMismatch);
+    }
+    Ok(())
+}
+
+async fn exchange_code_for_token(
+    provider: &ProviderConfig,
+    code: &str,
+    code_verifier: &str,
+    nonce: Option<&str>,
+) -> Result<TokenSet, AuthError> {
+    let client = auth_client(provider)?;
+
+    let mut form: Vec<(String, String)> = vec![
+        ("grant_type".to_string(), "authorization_code".to_string()),
+        ("code".to_string(), code.to_string()),
+        ("redirect_uri".to_string(), provider.redirect_uri.clone()),
+        ("client_id".to_string(), provider.client_id.clone()),
+        ("code_verifier".to_string(), code_verifier.to_string()),
+    ];
+
+    if let Some(extra) = &provider.extra_token_params {
+        for (key, value) in extra {
+            form.push((key.clone(), value.clone()));
+        }
+    }
+
+    let response = token_endpoint_request(&client, provider, form)
+        .send()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let (_, detail) = read_token_error(response).await;
+        return Err(AuthError::TokenExchangeFailed { status, detail });
+    }
+
+    let token: TokenResponse = response
+        .json()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    if let Some(nonce) = nonce {
+        let id_token = token.id_token.as_deref().ok_or(AuthError::MissingIdToken)?;
+        verify_id_token_nonce(id_token, nonce)?;
+    }
+
+    let expires_in = token.expires_in.unwrap_or(3600);
+    let expires_at = now_epoch() + expires_in;
+
+    Ok(TokenSet {
+        granted_scopes: granted_scopes(token.scope.as_deref(), provider),
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        expires_at,
+        storage_backend: None,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorizationResponse {
+    device_code: String,
+    user_code: String,
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: i64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Reads the start of a failed token endpoint response. Providers answer with an RFC 6749 error
+/// object, but proxies and misconfigured endpoints send whole HTML pages, so at most
+/// `TOKEN_ERROR_BODY_MAX` bytes are kept. Returns the parsed error and a message for the user.
+async fn read_token_error(
+    mut response: reqwest::Response,
+) -> (Option<TokenErrorResponse>, Option<String>) {
+    let mut body = Vec::new();
+    while body.len() < TOKEN_ERROR_BODY_MAX {
+        match response.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    body.truncate(TOKEN_ERROR_BODY_MAX);
+
+    if let Ok(error) = serde_json::from_slice::<TokenErrorResponse>(&body) {
+        let detail = match &error.error_description {
+            Some(description) => format!("{} ({description})", error.error),
+            None => error.error.clone(),
+        };
+        return (Some(error), Some(detail));
+    }
+
+    let text = String::from_utf8_lossy(&body);
+    let text = text.trim();
+    (None, (!text.is_empty()).then(|| text.to_string()))
+}
+
+async fn request_device_authorization(
+    provider: &ProviderConfig,
+) -> Result<DeviceAuthorizationResponse, AuthError> {
+    let endpoint = provider
+        .device_authorization_endpoint
+        .as_deref()
+        .ok_or(AuthError::DeviceFlowUnsupported)?;
+
+    let client = auth_client(provider)?;
+
+    let mut form: Vec<(String, String)> =
+        vec![("client_id".to_string(), provider.client_id.clone())];
+
+    if !provider.scopes.is_empty() {
+        form.push(("scope".to_string(), provider.scopes.join(" ")));
+    }
+
+    let response = client_form_request(&client, endpoint, provider, form)
+        .send()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let (_, detail) = read_token_error(response).await;
+        return Err(AuthError::TokenExchangeFailed { status, detail });
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))
+}
+
+/// Returns the approved device login together with its tokens.
+async fn poll_device_token(
+    state: &State<'_, AuthState>,
+) -> Result<(PendingDevice, TokenSet), AuthError> {
+    let initial = current_device(state)?.ok_or(AuthError::NoPendingDeviceLogin)?;
+    let client = auth_client(&initial.provider)?;
+    let mut interval = initial.interval;
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+
+        // Bail out if the login was restarted while we were sleeping.
+        let pending = current_device(state)?
+            .filter(|pending| pending.device_code == initial.device_code)
+            .ok_or(AuthError::NoPendingDeviceLogin)?;
+        if now_epoch() >= pending.expires_at {
+            return Err(AuthError::DeviceCodeExpired);
+        }
+
+        let provider = &pending.provider;
+        let mut form: Vec<(String, String)> = vec![
+            ("grant_type".to_string(), DEVICE_GRANT_TYPE.to_string()),
+            ("device_code".to_string(), pending.device_code.clone()),
+            ("client_id".to_string(), provider.client_id.clone()),
+        ];
+
+        if let Some(extra) = &provider.extra_token_params {
+            for (key, value) in extra {
+                form.push((key.clone(), value.clone()));
+            }
+        }
+
+        let response = token_endpoint_request(&client, provider, form)
+            .send()
+            .await
+            .map_err(|err| AuthError::Request(err.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            let token: TokenResponse = response
+                .json()
+                .await
+                .map_err(|err| AuthError::Request(err.to_string()))?;
+
+            let expires_in = token.expires_in.unwrap_or(3600);
+            let token_set = TokenSet {
+                granted_scopes: granted_scopes(token.scope.as_deref(), provider),
+                access_token: token.access_token,
+                refresh_token: token.refresh_token,
+                expires_at: now_epoch() + expires_in,
+                storage_backend: None,
+            };
+            return Ok((pending, token_set));
+        }
+
+        let (error, detail) = read_token_error(response).await;
+        match error.as_ref().map(|error| error.error.as_str()) {
+            Some("authorization_pending") => {}
+            Some("slow_down") => interval += DEVICE_SLOW_DOWN_SECS,
+            Some("expired_token") => return Err(AuthError::DeviceCodeExpired),
+            Some("access_denied") => {
+                let description = error
+                    .and_then(|error| error.error_description)
+                    .unwrap_or_else(|| "access_denied".to_string());
+                return Err(AuthError::AuthorizationDenied(description));
+            }
+            _ => return Err(AuthError::TokenExchangeFailed { status, detail }),
+        }
+    }
+}
+
+fn current_device(state: &State<'_, AuthState>) -> Result<Option<PendingDevice>, AuthError> {
+    state
+        .device
+        .lock()
+        .map(|guard| guard.clone())
+        .map_err(|_| AuthError::Storage("device lock failed".into()))
+}
+
+async fn refresh_tokens(
+    provider: &ProviderConfig,
+    refresh_token: &str,
+) -> Result<TokenSet, AuthError> {
+    let client = auth_client(provider)?;
+
+    let mut form: Vec<(String, String)> = vec![
+        ("grant_type".to_string(), "refresh_token".to_string()),
+        ("refresh_token".to_string(), refresh_token.to_string()),
+        ("client_id".to_string(), provider.client_id.clone()),
+    ];
+
+    if let Some(extra) = &provider.extra_token_params {
+        for (key, value) in extra {
+            form.push((key.clone(), value.clone()));
+        }
+    }
+
+    let response = send_refresh_request(&client, provider, form).await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        // 401 and invalid_grant mean the refresh token itself is dead (expired, revoked, or
+        // already rotated away); anything else may be transient.
+        let (error, detail) = read_token_error(response).await;
+        if status == StatusCode::UNAUTHORIZED
+            || error.is_some_and(|error| error.error == "invalid_grant")
+        {
+            return Err(AuthError::RefreshTokenRejected);
+        }
+        return Err(AuthError::TokenExchangeFailed { status, detail });
+    }
+
+    let token: TokenResponse = response
+        .json()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    let expires_in = token.expires_in.unwrap_or(3600);
+    let expires_at = now_epoch() + expires_in;
+
+    Ok(TokenSet {
+        granted_scopes: granted_scopes(token.scope.as_deref(), provider),
+        access_token: token.access_token,
+        refresh_token: token
+            .refresh_token
+            .or_else(|| Some(refresh_token.to_string())),
+        expires_at,
+        storage_backend: None,
+    })
+}
+
+/// Sends the refresh request, retrying network errors and 5xx responses with exponential
+/// backoff and jitter. 4xx responses (e.g. `invalid_grant`) are returned right away, and no retry
+/// starts once `REFRESH_RETRY_BUDGET` would be exceeded, so a refresh never hangs the UI for long.
+async fn send_refresh_request(
+    client: &reqwest::Client,
+    provider: &ProviderConfig,
+    form: Vec<(String, String)>,
+) -> Result<reqwest::Response, AuthError> {
+    let started = Instant::now();
+    let mut attempt = 1;
+    loop {
+        let result = token_endpoint_request(client, provider, form.clone())
+            .send()
+            .await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        if !retryable || attempt >= REFRESH_MAX_ATTEMPTS {
+            return result.map_err(|err| AuthError::Request(err.to_string()));
+        }
+
+        let delay = retry_delay(attempt);
+        if started.elapsed() + delay > REFRESH_RETRY_BUDGET {
+            return result.map_err(|err| AuthError::Request(err.to_string()));
+        }
+        match &result {
+            Ok(response) => log::warn!(
+                "Token refresh returned {}; retrying in {delay:?}",
+                response.status()
+            ),
+            Err(err) => log::warn!("Token refresh failed: {err}; retrying in {delay:?}"),
+        }
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// `REFRESH_RETRY_BASE_DELAY * 2^(attempt - 1)` plus up to half of that again as jitter, so
+/// clients that failed together don't retry in lockstep.
+fn retry_delay(attempt: u32) -> Duration {
+    let base = REFRESH_RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+    let jitter_ms = OsRng.next_u64() % (base.as_millis() as u64 / 2 + 1);
+    base + Duration::from_millis(jitter_ms)
+}
+
+/// Revokes the account's tokens at the provider (RFC 7009) if it has a `revocation_endpoint`.
+/// Best-effort: failures are logged and never block the local logout.
+async fn revoke_stored_tokens(app: &AppHandle, state: &State<'_, AuthState>, account_id: &str) {
+    let Ok(provider) = current_provider(app, state) else {
+        return;
+    };
+    let Some(endpoint) = provider.revocation_endpoint.as_deref() else {
+        return;
+    };
+    let tokens = match load_tokens(app, account_id) {
+        Ok(Some(tokens)) => tokens,
+        Ok(None) => return,
+        Err(err) => {
+            log::warn!("Could not load tokens for revocation: {err}");
+            return;
+        }
+    };
+
+    // Refresh token first: most providers then also invalidate the access tokens issued from it.
+    if let Some(refresh_token) = &tokens.refresh_token {
+        if let Err(err) = revoke_token(&provider, endpoint, refresh_token, "refresh_token").await {
+            log::warn!("Refresh token revocation failed: {err}");
+        }
+    }
+    if let Err(err) = revoke_token(&provider, endpoint, &tokens.access_token, "access_token").await
+    {
+        log::warn!("Access token revocation failed: {err}");
+    }
+}
+
+async fn revoke_token(
+    provider: &ProviderConfig,
+    endpoint: &str,
+    token: &str,
+    token_type_hint: &str,
+) -> Result<(), AuthError> {
+    let client = auth_client(provider)?;
+
+    let form: Vec<(String, String)> = vec![
+        ("token".to_string(), token.to_string()),
+        ("token_type_hint".to_string(), token_type_hint.to_string()),
+        ("client_id".to_string(), provider.client_id.clone()),
+    ];
+
+    let response = client_form_request(&client, endpoint, provider, form)
+        .send()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(AuthError::RevocationFailed(response.status()));
+    }
+
+    Ok(())
+}
+
+async fn introspect_token(
+    provider: &ProviderConfig,
+    endpoint: &str,
+    access_token: &str,
+) -> Result<serde_json::Value, AuthError> {
+    let client = auth_client(provider)?;
+
+    let form: Vec<(String, String)> = vec![
+        ("token".to_string(), access_token.to_string()),
+        ("token_type_hint".to_string(), "access_token".to_string()),
+        ("client_id".to_string(), provider.client_id.clone()),
+    ];
+
+    let response = client_form_request(&client, endpoint, provider, form)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(AuthError::IntrospectionFailed(response.status()));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|err| AuthError::Serialization(err.to_string()))
+}
+
+async fn fetch_user_info(
+    provider: &ProviderConfig,
+    endpoint: &str,
+    access_token: &str,
+) -> Result<String, AuthError> {
+    let client = auth_client(provider)?;
+
+    let response = client
+        .get(endpoint)
+        .bearer_auth(access_token)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(AuthError::UserInfoFailed(response.status()));
+    }
+
+    response
+        .text()
+        .await
+        .map_err(|err| AuthError::Request(err.to_string()))
+}
+
+fn load_tokens(app: &AppHandle, account_id: &str) -> Result<Option<TokenSet>, AuthError> {
+    let preference = load_storage_preference(app)?;
+    load_tokens_with(app, account_id, preference)
+}
+
+fn load_tokens_with(
+    app: &AppHandle,
+    account_id: &str,
+    preference: TokenStoragePreference,
+) -> Result<Option<TokenSet>, AuthError> {
+    read_tokens(app, &token_slot(app, account_id), preference)
+}
+
+/// Tokens and the account list saved before storage was namespaced per provider are adopted by
+/// the first provider to be current. Runs at startup and when a provider is activated, never
+/// from reads, and only until it succeeded once; does nothing without a provider. Tokens the
+/// namespace already holds win over legacy ones.
+pub fn migrate_legacy_storage(app: &AppHandle) -> Result<(), AuthError> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|err| AuthError::Storage(err.to_string()))?;
+    let migrated = store
+        .get(STORE_NAMESPACED_KEY)
+        .and_then(|value| value.as_bool());
+    if migrated == Some(true) {
+        return Ok(());
+    }
+    let Some(namespace) = current_namespace(app) else {
+        return Ok(());
+    };
+    let preference = load_storage_preference(app)?;
+    let legacy_accounts_key = accounts_key(None);
 
-    if now_epoch() - pending.created_at_epoch > PENDING_TTL.as_secs() as i64 {
-        clear_pending(state, app)?;
-        return Err(AuthError::PendingExpired);
+    // Tokens of the default account may predate the account list.
+    let mut legacy_ids = load_account_ids_at(app, &legacy_accounts_key)?;
+    if !legacy_ids.iter().any(|id| id == DEFAULT_ACCOUNT_ID) {
+        legacy_ids.insert(0, DEFAULT_ACCOUNT_ID.to_string());
     }
 
-    if pending.state != returned_state {
-        clear_pending(state, app)?;
-        return Err(AuthError::StateMismatch);
+    let accounts_key = accounts_key(Some(&namespace));
+    let mut account_ids = load_account_ids_at(app, &accounts_key)?;
+    let known = account_ids.len();
+    for account_id in legacy_ids {
+        let legacy = TokenSlot::new(None, &account_id);
+        let Some(mut tokens) = read_tokens(app, &legacy, preference)? else {
+            continue;
+        };
+
+        let slot = TokenSlot::new(Some(&namespace), &account_id);
+        let old_backend = tokens.storage_backend;
+        if read_tokens(app, &slot, preference)?.is_none() {
+            write_tokens(app, &slot, &mut tokens, preference)?;
+            log::info!("Moved tokens of account {account_id} into the provider namespace.");
+        }
+        if let Some(backend) = old_backend {
+            clear_slot(app, &legacy, backend)?;
+        }
+        if !account_ids.contains(&account_id) {
+            account_ids.push(account_id);
+        }
     }
 
-    let token_set = exchange_code_for_token(&pending.provider, &code, &pending.code_verifier).await?;
-    save_tokens(app, &token_set)?;
-    persist_provider(app, &pending.provider)?;
-    clear_pending(state, app)?;
-    emit_auth_changed(app, &token_set);
+    if account_ids.len() != known {
+        save_account_ids_at(app, &accounts_key, &account_ids)?;
+    }
 
-    drop(guard);
-    Ok(())
+    store.delete(&legacy_accounts_key);
+    store.set(STORE_NAMESPACED_KEY, true);
+    store
+        .save()
+        .map_err(|err| AuthError::Storage(err.to_string()))
 }
 
-fn emit_auth_changed(app: &AppHandle, tokens: &TokenSet) {
-    let status = AuthStatus {
-        is_authenticated: true,
-        expires_at: Some(tokens.expires_at),
-    };
-    let _ = app.emit("auth:changed", status);
+/// What `read_tokens` does after the keyring read failed.
+#[derive(Debug, PartialEq, Eq)]
+enum KeyringReadFallback {
+    /// Nothing is stored; the account is signed out.
+    SignedOut,
+    /// Read the app store instead.
+    Store,
+    /// Use the app store if it holds tokens, otherwise report the keyring as unavailable.
+    StoreIfPresent,
+    /// Fail with a `Storage` error.
+    Unavailable,
 }
 
-fn build_authorization_url(
-    provider: &ProviderConfig,
-    state_value: &str,
-    code_challenge: &str,
-) -> Result<String, AuthError> {
-    let mut url =
-        Url::parse(&provider.authorization_endpoint).map_err(|_| AuthError::InvalidRedirectUrl)?;
-
-    url.query_pairs_mut()
-        .append_pair("response_type", "code")
-        .append_pair("client_id", &provider.client_id)
-        .append_pair("redirect_uri", &provider.redirect_uri)
-        .append_pair("state", state_value)
-        .append_pair("code_challenge", code_challenge)
-        .append_pair("code_challenge_method", "S256");
-
-    if !provider.scopes.is_empty() {
-        url.query_pairs_mut()
-            .append_pair("scope", &provider.scopes.join(" "));
+/// In `Auto` mode, a keyring error other than `NoEntry` (typically a keychain that is still
+/// locked right after login) only falls back to the store when the tokens were saved there. If
+/// the store has nothing either, that is a retryable `Storage` error rather than "signed out",
+/// so a locked keychain never ends the session.
+fn keyring_read_fallback(
+    preference: TokenStoragePreference,
+    err: &keyring::Error,
+) -> KeyringReadFallback {
+    match (preference, err) {
+        // The keyring isn't consulted at all in this mode.
+        (TokenStoragePreference::Store, _) => KeyringReadFallback::Store,
+        (TokenStoragePreference::Keyring, keyring::Error::NoEntry) => {
+            KeyringReadFallback::SignedOut
+        }
+        (TokenStoragePreference::Keyring, _) => KeyringReadFallback::Unavailable,
+        (TokenStoragePreference::Auto, keyring::Error::NoEntry) => KeyringReadFallback::Store,
+        (TokenStoragePreference::Auto, _) => KeyringReadFallback::StoreIfPresent,
     }
+}
 
-    if let Some(extra) = &provider.extra_auth_params {
-        for (key, value) in extra {
-            url.query_pairs_mut().append_pair(key, value);
+fn read_tokens(
+    app: &AppHandle,
+    slot: &TokenSlot,
+    preference: TokenStoragePreference,
+) -> Result<Option<TokenSet>, AuthError> {
+    let mut keyring_error = None;
+    if preference != TokenStoragePreference::Store {
+        let entry = keyring::Entry::new(TOKEN_SERVICE, &slot.keyring_account)
+            .map_err(|err| AuthError::Storage(err.to_string()))?;
+
+        match entry.get_password() {
+            Ok(json) => {
+                let tokens: TokenSet = serde_json::from_str(&json)
+                    .map_err(|err| AuthError::Serialization(err.to_string()))?;
+                return Ok(Some(TokenSet {
+                    storage_backend: Some(StorageBackend::Keyring),
+                    ..tokens
+                }));
+            }
+            Err(err) => match keyring_read_fallback(preference, &err) {
+                KeyringReadFallback::SignedOut => return Ok(None),
+                KeyringReadFallback::Unavailable => {
+                    return Err(AuthError::Storage(format!("keyring unavailable: {err}")));
+                }
+                KeyringReadFallback::Store => {}
+                KeyringReadFallback::StoreIfPresent => keyring_error = Some(err),
+            },
         }
     }
 
-    Ok(url.to_string())
+    let tokens = load_tokens_store(app, &slot.store_key)?;
+    if let (None, Some(err)) = (&tokens, keyring_error) {
+        return Err(AuthError::Storage(format!(
+            "keyring unavailable, try again once it is unlocked: {err}"
+        )));
+    }
+    Ok(tokens.map(|tokens| TokenSet {
+        storage_backend: Some(StorageBackend::Store),
+        ..tokens
+    }))
 }
 
-#[derive(Debug, Deserialize)]
-struct TokenResponse {
-    access_token: String,
-    refresh_token: Option<String>,
-    expires_in: Option<i64>,
+/// Saves according to the storage preference and records where in `tokens.storage_backend`.
+fn save_tokens(app: &AppHandle, account_id: &str, tokens: &mut TokenSet) -> Result<(), AuthError> {
+    let preference = load_storage_preference(app)?;
+    save_tokens_with(app, account_id, tokens, preference)
 }
 
-async fn exchange_code_for_token(
-    provider: &ProviderConfig,
-    code: &str,
-    code_verifier: &str,
-) -> Result<TokenSet, AuthError> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .map_err(|err| AuthError::Request(err.to_string()))?;
+fn save_tokens_with(
+    app: &AppHandle,
+    account_id: &str,
+    tokens: &mut TokenSet,
+    preference: TokenStoragePreference,
+) -> Result<(), AuthError> {
+    remember_account(app, account_id)?;
+    cache_expiry(app, account_id, Some(tokens.expires_at));
+    write_tokens(app, &token_slot(app, account_id), tokens, preference)
+}
 
-    let mut form: Vec<(String, String)> = vec![
-        ("grant_type".to_string(), "authorization_code".to_string()),
-        ("code".to_string(), code.to_string()),
-        ("redirect_uri".to_string(), provider.redirect_uri.clone()),
-        ("client_id".to_string(), provider.client_id.clone()),
-        ("code_verifier".to_string(), code_verifier.to_string()),
-    ];
+fn write_tokens(
+    app: &AppHandle,
+    slot: &TokenSlot,
+    tokens: &mut TokenSet,
+    preference: TokenStoragePreference,
+) -> Result<(), AuthError> {
+    let json =
+        serde_json::to_string(tokens).map_err(|err| AuthError::Serialization(err.to_string()))?;
 
-    if let Some(secret) = &provider.client_secret {
-        form.push(("client_secret".to_string(), secret.clone()));
+    if preference != TokenStoragePreference::Store {
+        let entry = keyring::Entry::new(TOKEN_SERVICE, &slot.keyring_account)
+            .map_err(|err| AuthError::Storage(err.to_string()))?;
+
+        match entry.set_password(&json) {
+            Ok(()) => {
+                // A copy left by an earlier fallback would be read as current the next time the
+                // keyring is locked.
+                clear_tokens_store(app, &slot.store_key)?;
+                tokens.storage_backend = Some(StorageBackend::Keyring);
+                return Ok(());
+            }
+            Err(err) if preference == TokenStoragePreference::Keyring => {
+                return Err(AuthError::Storage(format!("keyring unavailable: {err}")));
+            }
+            Err(_) => {
+                log::warn!("Keychain unavailable; falling back to tauri-plugin-store for tokens.");
+            }
+        }
     }
 
-    if let Some(extra) = &provider.extra_token_params {
-        for (key, value) in extra {
-            form.push((key.clone(), value.clone()));
+    save_tokens_store(app, &slot.store_key, &json)?;
+    tokens.storage_backend = Some(StorageBackend::Store);
+    Ok(())
+}
+
+fn clear_backend(
+    app: &AppHandle,
+    account_id: &str,
+    backend: StorageBackend,
+) -> Result<(), AuthError> {
+    clear_slot(app, &token_slot(app, account_id), backend)
+}
+
+fn clear_slot(app: &AppHandle, slot: &TokenSlot, backend: StorageBackend) -> Result<(), AuthError> {
+    match backend {
+        StorageBackend::Keyring => {
+            if let Ok(entry) = keyring::Entry::new(TOKEN_SERVICE, &slot.keyring_account) {
+                let _ = entry.delete_password();
+            }
+            Ok(())
         }
+        StorageBackend::Store => clear_tokens_store(app, &slot.store_key),
     }
+}
 
-    let response = client
-        .post(&provider.token_endpoint)
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&form)
-        .send()
-        .await
-        .map_err(|err| AuthError::Request(err.to_string()))?;
-
-    if !response.status().is_success() {
-        return Err(AuthError::TokenExchangeFailed(response.status()));
+/// Removes the account's tokens. The provider config and any pending login are shared by all
+/// accounts, so they are only cleared, in memory and on disk, once the last account is gone.
+fn clear_tokens(app: &AppHandle, account_id: &str) -> Result<(), AuthError> {
+    let slot = token_slot(app, account_id);
+    clear_slot(app, &slot, StorageBackend::Keyring)?;
+    forget_account(app, account_id)?;
+    cache_expiry(app, account_id, None);
+    clear_slot(app, &slot, StorageBackend::Store)?;
+
+    if !other_accounts_remain(app, account_id)? {
+        app.state::<AuthState>().clear_shared(|| {
+            clear_provider_store(app)?;
+            clear_pending_store(app)
+        })?;
     }
+    Ok(())
+}
 
-    let token: TokenResponse = response
-        .json()
-        .await
-        .map_err(|err| AuthError::Request(err.to_string()))?;
+/// Decided from the account list rather than by reading tokens, so signing out never fails on a
+/// locked keyring. Untracked tokens of the default account count unless they are known to be
+/// gone.
+fn other_accounts_remain(app: &AppHandle, account_id: &str) -> Result<bool, AuthError> {
+    if !load_account_ids(app)?.is_empty() {
+        return Ok(true);
+    }
+    Ok(account_id != DEFAULT_ACCOUNT_ID
+        && !matches!(load_tokens(app, DEFAULT_ACCOUNT_ID), Ok(None)))
+}
 
-    let expires_in = token.expires_in.unwrap_or(3600);
-    let expires_at = now_epoch() + expires_in;
+fn cache_expiry(app: &AppHandle, account_id: &str, expires_at: Option<i64>) {
+    if let Ok(mut expiries) = app.state::<AuthState>().expiries.lock() {
+        expiries.insert(account_id.to_string(), expires_at);
+    }
+}
 
-    Ok(TokenSet {
-        access_token: token.access_token,
-        refresh_token: token.refresh_token,
-        expires_at,
-    })
+fn account_or_default(account_id: Option<String>) -> String {
+    account_id.unwrap_or_else(default_account_id)
 }
 
-async fn refresh_tokens(
-    provider: &ProviderConfig,
-    refresh_token: &str,
-) -> Result<TokenSet, AuthError> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(10))
-        .build()
-        .map_err(|err| AuthError::Request(err.to_string()))?;
+fn default_account_id() -> String {
+    DEFAULT_ACCOUNT_ID.to_string()
+}
 
-    let mut form: Vec<(String, String)> = vec![
-        ("grant_type".to_string(), "refresh_token".to_string()),
-        ("refresh_token".to_string(), refresh_token.to_string()),
-        ("client_id".to_string(), provider.client_id.clone()),
-    ];
+/// Where one account's tokens live: the keyring account, and the store key used when they fall
+/// back to the app store.
+struct TokenSlot {
+    keyring_account: String,
+    store_key: String,
+}
 
-    if let Some(secret) = &provider.client_secret {
-        form.push(("client_secret".to_string(), secret.clone()));
-    }
+impl TokenSlot {
+    /// Names are `oauth_tokens@<namespace>:<account>`. The default account drops the `:<account>`
+    /// suffix, and without a namespace the unprefixed names from before namespacing are used.
+    fn new(namespace: Option<&str>, account_id: &str) -> Self {
+        let name = |base: &str| {
+            let mut name = base.to_string();
+            if let Some(namespace) = namespace {
+                name.push('@');
+                name.push_str(namespace);
+            }
+            if account_id != DEFAULT_ACCOUNT_ID {
+                name.push(':');
+                name.push_str(account_id);
+            }
+            name
+        };
 
-    if let Some(extra) = &provider.extra_token_params {
-        for (key, value) in extra {
-            form.push((key.clone(), value.clone()));
+        Self {
+            keyring_account: name(TOKEN_ACCOUNT),
+            store_key: name(STORE_KEY),
         }
     }
+}
 
-    let response = client
-        .post(&provider.token_endpoint)
-        .header("Content-Type", "application/x-www-form-urlencoded")
-        .form(&form)
-        .send()
-        .await
-        .map_err(|err| AuthError::Request(err.to_string()))?;
+/// The slot of `account_id` under the current provider, or the legacy slot while no provider is
+/// configured.
+fn token_slot(app: &AppHandle, account_id: &str) -> TokenSlot {
+    TokenSlot::new(current_namespace(app).as_deref(), account_id)
+}
 
-    if !response.status().is_success() {
-        return Err(AuthError::TokenExchangeFailed(response.status()));
-    }
+fn current_namespace(app: &AppHandle) -> Option<String> {
+    let state = app.state::<AuthState>();
+    current_provider(app, &state)
+        .ok()
+        .map(|provider| provider_namespace(&provider))
+}
 
-    let token: TokenResponse = response
-        .json()
-        .await
-        .map_err(|err| AuthError::Request(err.to_string()))?;
+/// The account list is namespaced like the token slots (`oauth_accounts@<namespace>`), so it
+/// only lists accounts whose tokens the current provider can see.
+fn accounts_key(namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) => format!("{STORE_ACCOUNTS_KEY}@{namespace}"),
+        None => STORE_ACCOUNTS_KEY.to_string(),
+    }
+}
 
-    let expires_in = token.expires_in.unwrap_or(3600);
-    let expires_at = now_epoch() + expires_in;
+/// Stable per-provider id, so switching providers doesn't overwrite the previous provider's
+/// tokens.
+fn provider_namespace(provider: &ProviderConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(provider.client_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(provider.authorization_endpoint.as_bytes());
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
 
-    Ok(TokenSet {
-        access_token: token.access_token,
-        refresh_token: token
-            .refresh_token
-            .or_else(|| Some(refresh_token.to_string())),
-        expires_at,
-    })
+fn load_account_ids(app: &AppHandle) -> Result<Vec<String>, AuthError> {
+    load_account_ids_at(app, &accounts_key(current_namespace(app).as_deref()))
 }
 
-fn load_tokens(app: &AppHandle) -> Result<Option<TokenSet>, AuthError> {
-    let entry = keyring::Entry::new(TOKEN_SERVICE, TOKEN_ACCOUNT)
+fn load_account_ids_at(app: &AppHandle, key: &str) -> Result<Vec<String>, AuthError> {
+    let store = app
+        .store(STORE_PATH)
         .map_err(|err| AuthError::Storage(err.to_string()))?;
-
-    match entry.get_password() {
-        Ok(json) => {
-            let tokens = serde_json::from_str(&json)
-                .map_err(|err| AuthError::Serialization(err.to_string()))?;
-            Ok(Some(tokens))
-        }
-        Err(_) => load_tokens_store(app),
-    }
+    let Some(value) = store.get(key) else {
+        return Ok(Vec::new());
+    };
+    let json = value
+        .as_str()
+        .ok_or_else(|| AuthError::Serialization("invalid accounts format".into()))?;
+    serde_json::from_str(json).map_err(|err| AuthError::Serialization(err.to_string()))
 }
 
-fn save_tokens(app: &AppHandle, tokens: &TokenSet) -> Result<(), AuthError> {
-    let json =
-        serde_json::to_string(tokens).map_err(|err| AuthError::Serialization(err.to_string()))?;
+fn save_account_ids(app: &AppHandle, account_ids: &[String]) -> Result<(), AuthError> {
+    let key = accounts_key(current_namespace(app).as_deref());
+    save_account_ids_at(app, &key, account_ids)
+}
 
-    let entry = keyring::Entry::new(TOKEN_SERVICE, TOKEN_ACCOUNT)
+fn save_account_ids_at(
+    app: &AppHandle,
+    key: &str,
+    account_ids: &[String],
+) -> Result<(), AuthError> {
+    let json = serde_json::to_string(account_ids)
+        .map_err(|err| AuthError::Serialization(err.to_string()))?;
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|err| AuthError::Storage(err.to_string()))?;
+    store.set(key, json);
+    store
+        .save()
         .map_err(|err| AuthError::Storage(err.to_string()))?;
+    Ok(())
+}
 
-    if entry.set_password(&json).is_ok() {
+fn remember_account(app: &AppHandle, account_id: &str) -> Result<(), AuthError> {
+    let mut account_ids = load_account_ids(app)?;
+    if account_ids.iter().any(|id| id == account_id) {
         return Ok(());
     }
-
-    log::warn!("Keychain unavailable; falling back to tauri-plugin-store for tokens.");
-    save_tokens_store(app, &json)
+    account_ids.push(account_id.to_string());
+    save_account_ids(app, &account_ids)
 }
 
-fn clear_tokens(app: &AppHandle) -> Result<(), AuthError> {
-    if let Ok(entry) = keyring::Entry::new(TOKEN_SERVICE, TOKEN_ACCOUNT) {
-        let _ = entry.delete_password();
+fn forget_account(app: &AppHandle, account_id: &str) -> Result<(), AuthError> {
+    let mut account_ids = load_account_ids(app)?;
+    let before = account_ids.len();
+    account_ids.retain(|id| id != account_id);
+    if account_ids.len() == before {
+        return Ok(());
     }
-    clear_provider_store(app)?;
-    clear_pending_store(app)?;
-    clear_tokens_store(app)
+    save_account_ids(app, &account_ids)
+}
+
+fn load_storage_preference(app: &AppHandle) -> Result<TokenStoragePreference, AuthError> {
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|err| AuthError::Storage(err.to_string()))?;
+    let Some(value) = store.get(STORE_STORAGE_PREFERENCE_KEY) else {
+        return Ok(TokenStoragePreference::default());
+    };
+    serde_json::from_value(value).map_err(|err| AuthError::Serialization(err.to_string()))
+}
+
+fn persist_storage_preference(
+    app: &AppHandle,
+    preference: TokenStoragePreference,
+) -> Result<(), AuthError> {
+    let value = serde_json::to_value(preference)
+        .map_err(|err| AuthError::Serialization(err.to_string()))?;
+    let store = app
+        .store(STORE_PATH)
+        .map_err(|err| AuthError::Storage(err.to_string()))?;
+    store.set(STORE_STORAGE_PREFERENCE_KEY, value);
+    store
+        .save()
+        .map_err(|err| AuthError::Storage(err.to_string()))?;
+    Ok(())
 }
 
 fn persist_provider(app: &AppHandle, provider: &ProviderConfig) -> Result<(), AuthError> {
@@ -632,13 +2483,7 @@ fn clear_pending_store(app: &AppHandle) -> Result<(), AuthError> {
 fn load_pending(state: &State<'_, AuthState>, app: &AppHandle) -> Result<PendingAuthRecord, AuthError> {
     if let Ok(guard) = state.pending.lock() {
         if let Some(pending) = guard.as_ref() {
-            let elapsed = pending.created_at.elapsed().as_secs() as i64;
-            return Ok(PendingAuthRecord {
-                state: pending.state.clone(),
-                code_verifier: pending.code_verifier.clone(),
-                provider: pending.provider.clone(),
-                created_at_epoch: now_epoch().saturating_sub(elapsed),
-            });
+            return Ok(pending.clone());
         }
     }
 
@@ -652,40 +2497,54 @@ fn clear_pending(state: &State<'_, AuthState>, app: &AppHandle) -> Result<(), Au
     clear_pending_store(app)
 }
 
-fn load_tokens_store(app: &AppHandle) -> Result<Option<TokenSet>, AuthError> {
+fn load_tokens_store(app: &AppHandle, key: &str) -> Result<Option<TokenSet>, AuthError> {
     let store = app
         .store(STORE_PATH)
         .map_err(|err| AuthError::Storage(err.to_string()))?;
 
-    let Some(value) = store.get(STORE_KEY) else {
+    let Some(value) = store.get(key) else {
         return Ok(None);
     };
 
-    let json = value
+    let value = value
         .as_str()
         .ok_or_else(|| AuthError::Serialization("invalid token format".into()))?;
 
-    let tokens =
-        serde_json::from_str(json).map_err(|err| AuthError::Serialization(err.to_string()))?;
+    if store_crypto::is_encrypted(value) {
+        let json = store_crypto::decrypt(value)?;
+        let tokens =
+            serde_json::from_str(&json).map_err(|err| AuthError::Serialization(err.to_string()))?;
+        return Ok(Some(tokens));
+    }
+
+    // Entries written before the fallback was encrypted are plain JSON; re-save them encrypted.
+    let tokens: TokenSet =
+        serde_json::from_str(value).map_err(|err| AuthError::Serialization(err.to_string()))?;
+    if let Err(err) = save_tokens_store(app, key, value) {
+        log::warn!("Failed to encrypt stored tokens: {err}");
+    }
     Ok(Some(tokens))
 }
 
-fn save_tokens_store(app: &AppHandle, json: &str) -> Result<(), AuthError> {
+fn save_tokens_store(app: &AppHandle, key: &str, json: &str) -> Result<(), AuthError> {
+    let encrypted = store_crypto::encrypt(json)?;
     let store = app
         .store(STORE_PATH)
         .map_err(|err| AuthError::Storage(err.to_string()))?;
-    store.set(STORE_KEY, json);
+    store.set(key, encrypted);
     store
         .save()
         .map_err(|err| AuthError::Storage(err.to_string()))?;
     Ok(())
 }
 
-fn clear_tokens_store(app: &AppHandle) -> Result<(), AuthError> {
+fn clear_tokens_store(app: &AppHandle, key: &str) -> Result<(), AuthError> {
     let store = app
         .store(STORE_PATH)
         .map_err(|err| AuthError::Storage(err.to_string()))?;
-    store.delete(STORE_KEY);
+    if !store.delete(key) {
+        return Ok(());
+    }
     store
         .save()
         .map_err(|err| AuthError::Storage(err.to_string()))?;
@@ -698,7 +2557,11 @@ fn random_urlsafe(size: usize) -> String {
     URL_SAFE_NO_PAD.encode(buffer)
 }
 
-fn pkce_challenge(verifier: &str) -> String {
+fn pkce_challenge(verifier: &str, method: PkceMethod) -> String {
+    if method == PkceMethod::Plain {
+        return verifier.to_string();
+    }
+
     let mut hasher = Sha256::new();
     hasher.update(verifier.as_bytes());
     let digest = hasher.finalize();
@@ -742,3 +2605,364 @@ impl Drop for ProcessingGuard<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn test_provider() -> ProviderConfig {
+        serde_json::from_value(serde_json::json!({
+            "client_id": "vision-desktop",
+            "authorization_endpoint": "https://auth.example.com/authorize",
+            "token_endpoint": "https://auth.example.com/token",
+            "redirect_uri": "vision://auth/callback",
+            "scopes": ["openid"],
+        }))
+        .unwrap()
+    }
+
+    fn pending_created_at(created_at_epoch: i64) -> PendingAuthRecord {
+        PendingAuthRecord {
+            account_id: default_account_id(),
+            state: "state".to_string(),
+            code_verifier: "verifier".to_string(),
+            nonce: None,
+            provider: test_provider(),
+            created_at_epoch,
+        }
+    }
+
+    #[test]
+    fn pending_login_past_the_ttl_is_expired() {
+        let now = 1_000_000;
+        let ttl = PENDING_TTL.as_secs() as i64;
+        assert!(matches!(
+            ensure_pending_fresh(&pending_created_at(now - ttl - 1), now),
+            Err(AuthError::PendingExpired)
+        ));
+    }
+
+    #[test]
+    fn pending_login_exactly_at_the_ttl_is_accepted() {
+        let now = 1_000_000;
+        let ttl = PENDING_TTL.as_secs() as i64;
+        assert!(ensure_pending_fresh(&pending_created_at(now - ttl), now).is_ok());
+        assert!(ensure_pending_fresh(&pending_created_at(now), now).is_ok());
+    }
+
+    #[test]
+    fn account_list_is_namespaced_like_the_token_slots() {
+        let namespace = provider_namespace(&test_provider());
+        let slot = TokenSlot::new(Some(&namespace), DEFAULT_ACCOUNT_ID);
+        assert_eq!(
+            accounts_key(Some(&namespace)),
+            slot.store_key.replacen(STORE_KEY, STORE_ACCOUNTS_KEY, 1)
+        );
+        assert_eq!(accounts_key(None), STORE_ACCOUNTS_KEY);
+        assert_ne!(accounts_key(Some("other")), accounts_key(Some(&namespace)));
+    }
+
+    /// A token endpoint on 127.0.0.1 that counts the refresh requests it answers.
+    async fn serve_token_endpoint(requests: Arc<AtomicUsize>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_ascii_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .and_then(|value| value.trim().parse().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0u8; length];
+                let _ = stream.read_exact(&mut body).await;
+
+                requests.fetch_add(1, Ordering::SeqCst);
+                // Slow enough that a second caller queues up behind the first.
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let json =
+                    r#"{"access_token":"refreshed","token_type":"Bearer","expires_in":3600}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+Content-Length: {}\r\nConnection: close\r\n\r\n{json}",
+                    json.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    /// Runs `fresh_tokens_with` against in-memory storage holding a token about to expire, with
+    /// the real refresh request going to `provider`.
+    async fn fresh_tokens_from(
+        locks: &RefreshLocks,
+        stored: &Mutex<HashMap<String, TokenSet>>,
+        provider: &ProviderConfig,
+        account_id: &str,
+    ) -> TokenSet {
+        fresh_tokens_with(
+            locks,
+            account_id,
+            || Ok(stored.lock().unwrap().get(account_id).cloned()),
+            |refresh_token| async move {
+                let refreshed = refresh_tokens(provider, &refresh_token).await?;
+                stored
+                    .lock()
+                    .unwrap()
+                    .insert(account_id.to_string(), refreshed.clone());
+                Ok(refreshed)
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    fn expiring_tokens() -> TokenSet {
+        TokenSet {
+            access_token: "old".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: now_epoch() + 10,
+            granted_scopes: None,
+            storage_backend: None,
+        }
+    }
+
+    fn refresh_concurrently(account_ids: [&str; 2]) -> (usize, [TokenSet; 2]) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let locks = RefreshLocks::default();
+        let stored = Mutex::new(
+            account_ids
+                .iter()
+                .map(|account_id| (account_id.to_string(), expiring_tokens()))
+                .collect(),
+        );
+        let tokens = tauri::async_runtime::block_on(async {
+            let mut provider = test_provider();
+            provider.token_endpoint = serve_token_endpoint(requests.clone()).await;
+            let (first, second) = futures_util::future::join(
+                fresh_tokens_from(&locks, &stored, &provider, account_ids[0]),
+                fresh_tokens_from(&locks, &stored, &provider, account_ids[1]),
+            )
+            .await;
+            [first, second]
+        });
+        (requests.load(Ordering::SeqCst), tokens)
+    }
+
+    #[test]
+    fn concurrent_refreshes_of_one_account_make_one_request() {
+        let (requests, tokens) = refresh_concurrently(["default", "default"]);
+        assert_eq!(requests, 1);
+        assert_eq!(tokens[0].access_token, "refreshed");
+        assert_eq!(tokens[1].access_token, "refreshed");
+    }
+
+    #[test]
+    fn refreshes_of_different_accounts_do_not_coalesce() {
+        let (requests, _) = refresh_concurrently(["default", "work"]);
+        assert_eq!(requests, 2);
+    }
+
+    /// Runs `fresh_tokens_with` on `tokens` with a refresh that fails with `error`.
+    fn fresh_tokens_failing_with(
+        tokens: TokenSet,
+        error: AuthError,
+    ) -> Result<TokenSet, AuthError> {
+        let locks = RefreshLocks::default();
+        tauri::async_runtime::block_on(fresh_tokens_with(
+            &locks,
+            DEFAULT_ACCOUNT_ID,
+            || Ok(Some(tokens)),
+            |_| async { Err(error) },
+        ))
+    }
+
+    #[test]
+    fn failed_refresh_inside_the_window_keeps_the_valid_token() {
+        let tokens = fresh_tokens_failing_with(
+            expiring_tokens(),
+            AuthError::Request("connection reset".to_string()),
+        )
+        .unwrap();
+        assert_eq!(tokens.access_token, "old");
+    }
+
+    #[test]
+    fn rejected_refresh_token_is_reported_inside_the_window() {
+        let result = fresh_tokens_failing_with(expiring_tokens(), AuthError::RefreshTokenRejected);
+        assert!(matches!(result, Err(AuthError::RefreshTokenRejected)));
+    }
+
+    #[test]
+    fn failed_refresh_of_an_expired_token_is_reported() {
+        let mut tokens = expiring_tokens();
+        tokens.expires_at = now_epoch() - 1;
+        let result =
+            fresh_tokens_failing_with(tokens, AuthError::Request("connection reset".to_string()));
+        assert!(matches!(result, Err(AuthError::Request(_))));
+    }
+
+    #[test]
+    fn tokens_with_a_refresh_token_are_refreshed_inside_the_window() {
+        let now = 1_000_000;
+        assert_eq!(token_freshness(now, now, true), TokenFreshness::Refresh);
+        assert_eq!(
+            token_freshness(now + REFRESH_WINDOW_SECS, now, true),
+            TokenFreshness::Refresh
+        );
+        assert_eq!(
+            token_freshness(now + REFRESH_WINDOW_SECS + 1, now, true),
+            TokenFreshness::Usable
+        );
+    }
+
+    #[test]
+    fn tokens_without_a_refresh_token_are_used_until_they_expire() {
+        let now = 1_000_000;
+        assert_eq!(token_freshness(now, now, false), TokenFreshness::Expired);
+        assert_eq!(
+            token_freshness(now + REFRESH_WINDOW_SECS, now, false),
+            TokenFreshness::Usable
+        );
+        assert_eq!(
+            token_freshness(now + REFRESH_WINDOW_SECS + 1, now, false),
+            TokenFreshness::Usable
+        );
+    }
+
+    #[test]
+    fn refresh_after_signing_out_the_last_account_needs_a_provider() {
+        let state = AuthState::new();
+        let persisted = Mutex::new(None);
+        let stored = Mutex::new(None);
+
+        // Signed in: the login made its provider current and persisted it with the tokens.
+        *state.provider.lock().unwrap() = Some(test_provider());
+        *persisted.lock().unwrap() = Some(test_provider());
+        *stored.lock().unwrap() = Some(expiring_tokens());
+        *state.pending.lock().unwrap() = Some(pending_created_at(now_epoch()));
+
+        // Signed out of the only account, as `clear_tokens` does it.
+        *stored.lock().unwrap() = None;
+        state
+            .clear_shared(|| {
+                *persisted.lock().unwrap() = None;
+                Ok(())
+            })
+            .unwrap();
+        assert!(state.pending.lock().unwrap().is_none());
+
+        let result = tauri::async_runtime::block_on(refresh_if_needed_with(
+            &state.refresh,
+            DEFAULT_ACCOUNT_ID,
+            || current_provider_with(&state, || Ok(persisted.lock().unwrap().clone())),
+            || Ok(stored.lock().unwrap().clone()),
+            |_, _| async { panic!("nothing left to refresh") },
+        ));
+        assert!(matches!(result, Err(AuthError::ProviderConfigMissing)));
+    }
+
+    #[test]
+    fn missing_keyring_entry_falls_back_to_the_store_only_in_auto_mode() {
+        let err = keyring::Error::NoEntry;
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Auto, &err),
+            KeyringReadFallback::Store
+        );
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Keyring, &err),
+            KeyringReadFallback::SignedOut
+        );
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Store, &err),
+            KeyringReadFallback::Store
+        );
+    }
+
+    #[test]
+    fn locked_keyring_is_never_read_as_signed_out() {
+        let locked = || keyring::Error::NoStorageAccess("keychain is locked".into());
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Auto, &locked()),
+            KeyringReadFallback::StoreIfPresent
+        );
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Keyring, &locked()),
+            KeyringReadFallback::Unavailable
+        );
+        assert_eq!(
+            keyring_read_fallback(TokenStoragePreference::Store, &locked()),
+            KeyringReadFallback::Store
+        );
+    }
+
+    #[test]
+    fn sleeping_past_the_token_lifetime_checks_on_the_next_poll() {
+        let last_poll = 1_000_000;
+        let expires_at = last_poll + 120;
+        let now = last_poll + 3_600;
+
+        let tick = watcher_tick(last_poll, last_poll - 10, now);
+        let WatcherTick::Resumed { jump } = tick else {
+            panic!("expected a resume, got {tick:?}");
+        };
+        assert!(jump > expires_at - last_poll);
+    }
+
+    #[test]
+    fn normal_polls_wait_for_the_check_interval() {
+        let last_poll = 1_000_000;
+        let poll = WAKE_POLL_INTERVAL.as_secs() as i64;
+        let check = EXPIRY_CHECK_INTERVAL.as_secs() as i64;
+
+        assert_eq!(
+            watcher_tick(last_poll, last_poll - 10, last_poll + poll),
+            WatcherTick::Idle
+        );
+        // A poll that runs a little late is not a resume.
+        assert_eq!(
+            watcher_tick(
+                last_poll,
+                last_poll - 10,
+                last_poll + poll + WAKE_JUMP_THRESHOLD_SECS
+            ),
+            WatcherTick::Idle
+        );
+        assert_eq!(
+            watcher_tick(last_poll, last_poll + poll - check, last_poll + poll),
+            WatcherTick::Check
+        );
+    }
+
+    #[test]
+    fn a_later_refresh_is_not_coalesced() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let locks = RefreshLocks::default();
+        let stored = Mutex::new(HashMap::new());
+        tauri::async_runtime::block_on(async {
+            let mut provider = test_provider();
+            provider.token_endpoint = serve_token_endpoint(requests.clone()).await;
+            for _ in 0..2 {
+                stored
+                    .lock()
+                    .unwrap()
+                    .insert("default".to_string(), expiring_tokens());
+                fresh_tokens_from(&locks, &stored, &provider, "default").await;
+            }
+        });
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,69 @@
+//! Encryption for tokens that end up in `auth.json` when no keychain is available.
+//!
+//! The key is derived from the OS machine id, so a copied `auth.json` is useless on another
+//! machine. This does not protect against other processes of the same user, but it keeps
+//! refresh tokens out of plaintext backups and sync folders.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use super::AuthError;
+
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const KEY_CONTEXT: &[u8] = b"vision-desktop token store v1";
+const NONCE_LEN: usize = 24;
+
+/// Returns true if `value` was written by `encrypt`; anything else is a legacy plaintext entry.
+pub(super) fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+pub(super) fn encrypt(plaintext: &str) -> Result<String, AuthError> {
+    let cipher = cipher()?;
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| AuthError::Storage("failed to encrypt tokens".into()))?;
+
+    let mut payload = nonce.to_vec();
+    payload.extend_from_slice(&ciphertext);
+    Ok(format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(payload)))
+}
+
+pub(super) fn decrypt(value: &str) -> Result<String, AuthError> {
+    let encoded = value
+        .strip_prefix(ENCRYPTED_PREFIX)
+        .ok_or_else(|| AuthError::Serialization("token entry is not encrypted".into()))?;
+    let payload = STANDARD
+        .decode(encoded)
+        .map_err(|err| AuthError::Serialization(err.to_string()))?;
+    if payload.len() < NONCE_LEN {
+        return Err(AuthError::Serialization(
+            "encrypted token entry too short".into(),
+        ));
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext = cipher()?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| AuthError::Storage("failed to decrypt tokens".into()))?;
+    String::from_utf8(plaintext).map_err(|err| AuthError::Serialization(err.to_string()))
+}
+
+fn cipher() -> Result<XChaCha20Poly1305, AuthError> {
+    let machine_id = machine_uid::get()
+        .map_err(|err| AuthError::Storage(format!("machine id unavailable: {err}")))?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_CONTEXT);
+    hasher.update(machine_id.trim().as_bytes());
+    let key = hasher.finalize();
+
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
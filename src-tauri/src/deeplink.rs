@@ -1,84 +1,256 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Mutex;
 
+use serde::Serialize;
 use tauri::{AppHandle, Emitter, Manager, State};
 use tauri_plugin_deep_link::DeepLinkExt;
 use url::Url;
 
 use crate::auth;
 
-const SCHEME: &str = "vision";
-const CALLBACK_HOST: &str = "auth";
-const CALLBACK_PATH: &str = "/callback";
+// Overridable at build time so side-by-side channels (e.g. `vision-beta://`) don't steal each
+// other's links. The scheme must also be listed under `plugins.deep-link` in tauri.conf.json.
+const SCHEME: &str = build_config(option_env!("VISION_DEEPLINK_SCHEME"), "vision");
+const CALLBACK_HOST: &str = build_config(option_env!("VISION_DEEPLINK_CALLBACK_HOST"), "auth");
+const CALLBACK_PATH: &str = build_config(option_env!("VISION_DEEPLINK_CALLBACK_PATH"), "/callback");
+
+const MAX_PARAM_LEN: usize = 128;
+const NOT_FOUND_ROUTE: &str = "not-found";
+
+/// Routes accepted before the frontend has called `deeplink_register_routes`; mirrors
+/// `DEEP_LINK_ROUTES` in `App.tsx`. A `:name` segment captures that part of the link into
+/// `params`.
+const DEFAULT_ROUTES: &[&str] = &[
+    "home",
+    "projects",
+    "news",
+    "explore",
+    "media",
+    "settings",
+    "settings-debug",
+    "profile",
+    "editor",
+    "analytics",
+    "calendar",
+    "admin",
+    "roles",
+    "members",
+    "player-applications",
+    "applications",
+    "projects/:id",
+    "editor/:docId",
+    "profile/:userId",
+];
+
+/// Payload of `app:navigate`: `vision://projects?id=42#files` becomes route `projects`,
+/// params `{ id: "42" }` and fragment `files`. A link matching no registered route becomes route
+/// `not-found` with the raw link in `original`.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeepLinkRoute {
+    pub route: String,
+    pub params: HashMap<String, String>,
+    pub fragment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original: Option<String>,
+}
+
+impl DeepLinkRoute {
+    fn not_found(url: &Url) -> Self {
+        Self {
+            route: NOT_FOUND_ROUTE.to_string(),
+            params: HashMap::new(),
+            fragment: None,
+            original: Some(url.to_string()),
+        }
+    }
+}
 
 pub struct DeepLinkState {
-    last_route: Mutex<Option<String>>,
+    last_route: Mutex<Option<DeepLinkRoute>>,
+    /// Set alongside every `app:navigate`; the frontend takes it once its listener is attached,
+    /// so a link that opened the app is not lost to the startup race.
+    pending_route: Mutex<Option<DeepLinkRoute>>,
+    routes: Mutex<HashSet<String>>,
+    /// Whether the scheme is registered, checked in `setup` (before any listener exists) and
+    /// again after `deeplink_register`, so the frontend can ask for it once it has mounted.
+    registered: Mutex<Option<bool>>,
 }
 
 impl DeepLinkState {
     pub fn new() -> Self {
         Self {
             last_route: Mutex::new(None),
+            pending_route: Mutex::new(None),
+            routes: Mutex::new(
+                DEFAULT_ROUTES
+                    .iter()
+                    .map(|route| route.to_string())
+                    .collect(),
+            ),
+            registered: Mutex::new(None),
+        }
+    }
+
+    fn set_routes(&self, routes: Vec<String>) {
+        if let Ok(mut guard) = self.routes.lock() {
+            *guard = routes
+                .into_iter()
+                .map(|route| route.trim_matches('/').to_string())
+                .filter(|route| !route.is_empty())
+                .collect();
         }
     }
 
-    fn set_route(&self, route: Option<String>) {
+    /// The route for a non-callback link, falling back to `not-found` so the user sees why
+    /// nothing opened.
+    fn resolve_route(&self, url: &Url) -> DeepLinkRoute {
+        self.extract_route(url)
+            .unwrap_or_else(|| DeepLinkRoute::not_found(url))
+    }
+
+    fn extract_route(&self, url: &Url) -> Option<DeepLinkRoute> {
+        let segments = route_segments(url)?;
+        let (route, captures) = {
+            let routes = self.routes.lock().ok()?;
+            routes
+                .iter()
+                .find_map(|pattern| match_route(pattern, &segments))?
+        };
+
+        let mut params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        params.extend(captures);
+        Some(DeepLinkRoute {
+            route,
+            params,
+            fragment: url.fragment().map(str::to_string),
+            original: None,
+        })
+    }
+
+    fn set_route(&self, route: Option<DeepLinkRoute>) {
+        if let Ok(mut guard) = self.pending_route.lock() {
+            *guard = route.clone();
+        }
         if let Ok(mut guard) = self.last_route.lock() {
             *guard = route;
         }
     }
 
-    fn get_route(&self) -> Option<String> {
+    fn take_pending_route(&self) -> Option<DeepLinkRoute> {
+        self.pending_route
+            .lock()
+            .ok()
+            .and_then(|mut guard| guard.take())
+    }
+
+    fn get_route(&self) -> Option<DeepLinkRoute> {
         self.last_route.lock().ok().and_then(|guard| guard.clone())
     }
+
+    fn take_route(&self) -> Option<DeepLinkRoute> {
+        let route = self
+            .last_route
+            .lock()
+            .ok()
+            .and_then(|mut guard| guard.take());
+        self.take_pending_route();
+        route
+    }
+}
+
+/// Replaces the set of routes deep links may navigate to. The frontend calls this at startup
+/// with its page list, so new screens don't need a backend change.
+#[tauri::command]
+pub fn deeplink_register_routes(state: State<'_, DeepLinkState>, routes: Vec<String>) {
+    state.set_routes(routes);
+}
+
+/// With `take`, the route is cleared as it is returned, so the same link can't navigate twice.
+#[tauri::command]
+pub fn deeplink_get_current_route(
+    state: State<'_, DeepLinkState>,
+    take: Option<bool>,
+) -> Option<DeepLinkRoute> {
+    if take.unwrap_or(false) {
+        state.take_route()
+    } else {
+        state.get_route()
+    }
+}
+
+/// Forgets the last deep-link route once the frontend has handled it.
+#[tauri::command]
+pub fn deeplink_clear_route(state: State<'_, DeepLinkState>) {
+    state.set_route(None);
 }
 
+/// Returns the route of the deep link that opened or last focused the app and clears it. Call
+/// once the router is mounted; later links arrive through `app:navigate`.
 #[tauri::command]
-pub fn deeplink_get_current_route(state: State<'_, DeepLinkState>) -> Option<String> {
-    state.get_route()
+pub fn deeplink_consume_pending_route(state: State<'_, DeepLinkState>) -> Option<DeepLinkRoute> {
+    state.take_pending_route()
+}
+
+/// Whether this build is the OS handler for `vision://`. When it isn't, OAuth callbacks never
+/// arrive and the frontend should offer the loopback login instead. Platforms that register the
+/// scheme through the bundle (macOS, mobile) can't be queried and report `true`. Answered from
+/// the check made at startup; call it on mount rather than waiting for an event.
+#[tauri::command]
+pub fn deeplink_check_registration(app: AppHandle, state: State<'_, DeepLinkState>) -> bool {
+    let Ok(mut registered) = state.registered.lock() else {
+        return is_scheme_registered(&app);
+    };
+    *registered.get_or_insert_with(|| is_scheme_registered(&app))
+}
+
+/// Registers this executable as the `vision://` handler. Only Windows and Linux support this at
+/// runtime; elsewhere the scheme comes from the app bundle.
+#[tauri::command]
+pub fn deeplink_register(app: AppHandle, state: State<'_, DeepLinkState>) -> Result<(), String> {
+    let result = app
+        .deep_link()
+        .register(SCHEME)
+        .map_err(|err| format!("failed to register {SCHEME}:// handler: {err}"));
+    if let Ok(mut registered) = state.registered.lock() {
+        *registered = Some(is_scheme_registered(&app));
+    }
+    result
+}
+
+fn is_scheme_registered(app: &AppHandle) -> bool {
+    match app.deep_link().is_registered(SCHEME) {
+        Ok(registered) => registered,
+        Err(tauri_plugin_deep_link::Error::UnsupportedPlatform) => true,
+        Err(err) => {
+            log::warn!("Failed to query {SCHEME}:// registration: {err}");
+            false
+        }
+    }
 }
 
 pub fn setup_deeplinks(app: &AppHandle) {
     let app_handle = app.clone();
 
+    // Nothing listens for events this early, so the result is kept for
+    // `deeplink_check_registration`.
+    let registered = is_scheme_registered(app);
+    if !registered {
+        log::warn!("This build is not the {SCHEME}:// handler; OAuth callbacks won't arrive.");
+    }
+    if let Ok(mut state) = app.state::<DeepLinkState>().registered.lock() {
+        *state = Some(registered);
+    }
+
     app.deep_link().on_open_url(move |event| {
         let app = app_handle.clone();
-        let urls = event.urls().to_vec();
-        tauri::async_runtime::spawn(async move {
-            for url in urls {
-                if is_oauth_callback(&url) {
-                    let state = app.state::<auth::AuthState>();
-                    if let Err(err) = auth::handle_callback_url(&app, &state, url).await {
-                        let _ = app.emit("auth:error", err.to_string());
-                    }
-                    focus_main_window(&app);
-                } else if let Some(route) = extract_route(&url) {
-                    let state = app.state::<DeepLinkState>();
-                    state.set_route(Some(route.clone()));
-                    let _ = app.emit("app:navigate", route);
-                    focus_main_window(&app);
-                }
-            }
-        });
+        let urls = event.urls();
+        tauri::async_runtime::spawn(async move { handle_links(&app, urls).await });
     });
 
     // Handle deeplinks when the app launches (macOS/iOS/Android).
     if let Ok(Some(urls)) = app.deep_link().get_current() {
-        for url in urls {
-            if is_oauth_callback(&url) {
-                let app = app.clone();
-                tauri::async_runtime::spawn(async move {
-                    let state = app.state::<auth::AuthState>();
-                    if let Err(err) = auth::handle_callback_url(&app, &state, url).await {
-                        let _ = app.emit("auth:error", err.to_string());
-                    }
-                    focus_main_window(&app);
-                });
-            } else if let Some(route) = extract_route(&url) {
-                let state = app.state::<DeepLinkState>();
-                state.set_route(Some(route.clone()));
-                let _ = app.emit("app:navigate", route);
-            }
-        }
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move { handle_links(&app, urls).await });
     }
 
     // Handle deeplinks passed as CLI arguments (Windows/Linux).
@@ -87,83 +259,271 @@ pub fn setup_deeplinks(app: &AppHandle) {
 
 /// When running as a single-instance app on Windows/Linux, deep links are delivered to the already
 /// running process via the single-instance callback args. We need to parse and handle them here,
-/// otherwise OAuth callbacks won't be processed if the app was already open.
+/// otherwise OAuth callbacks won't be processed if the app was already open. Routes are stored
+/// and emitted like any other link, after the window has been restored.
 pub fn handle_single_instance_args(app: &AppHandle, args: Vec<String>) {
     let app_handle = app.clone();
 
-    tauri::async_runtime::spawn(async move {
-        for raw in args {
-            let candidate = raw.trim_matches('"').trim().to_string();
-            if !candidate.starts_with("vision:") {
-                continue;
-            }
+    let urls = links_from_args(&args);
 
-            let url = match Url::parse(&candidate) {
-                Ok(value) => value,
-                Err(_) => continue,
-            };
+    tauri::async_runtime::spawn(async move { handle_links(&app_handle, urls).await });
+}
 
-            if is_oauth_callback(&url) {
-                let state = app_handle.state::<auth::AuthState>();
-                if let Err(err) = auth::handle_callback_url(&app_handle, &state, url).await {
-                    let _ = app_handle.emit("auth:error", err.to_string());
+/// Debug builds only: handles `url` exactly as if the OS had opened it, so UI tests can drive
+/// routing and OAuth callbacks without a registered scheme handler.
+#[tauri::command]
+pub async fn deeplink_simulate(app: AppHandle, url: String) -> Result<(), String> {
+    if !cfg!(debug_assertions) {
+        return Err("only available in debug builds".into());
+    }
+
+    let url = Url::parse(url.trim()).map_err(|err| format!("invalid url: {err}"))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("expected a {SCHEME}:// link"));
+    }
+    handle_links(&app, vec![url]).await;
+    Ok(())
+}
+
+/// Completes the login for an OAuth callback, or navigates to the link's route, and brings the
+/// window forward.
+async fn handle_links(app: &AppHandle, urls: Vec<Url>) {
+    dispatch_links(
+        urls,
+        |url| async move {
+            complete_login(app, url).await;
+            focus_main_window(app);
+        },
+        |url| {
+            // Restore the window before navigating, so the route change is what the user sees
+            // rather than something that happened while it was hidden.
+            focus_main_window(app);
+            let route = app.state::<DeepLinkState>().resolve_route(&url);
+            navigate(app, route);
+        },
+    )
+    .await;
+}
+
+/// Handles one batch of links in order: OAuth callbacks go to `on_callback`, which is awaited
+/// before the next link, everything else to `on_route`. Duplicates and every callback after the
+/// first are dropped (see `unique_links`).
+async fn dispatch_links<Callback, Completed>(
+    urls: impl IntoIterator<Item = Url>,
+    mut on_callback: Callback,
+    mut on_route: impl FnMut(Url),
+) where
+    Callback: FnMut(Url) -> Completed,
+    Completed: std::future::Future<Output = ()>,
+{
+    for url in unique_links(urls) {
+        if is_oauth_callback(&url) {
+            on_callback(url).await;
+        } else {
+            on_route(url);
+        }
+    }
+}
+
+async fn complete_login(app: &AppHandle, url: Url) {
+    let state = app.state::<auth::AuthState>();
+    if let Err(err) = auth::handle_callback_url(app, &state, url).await {
+        let _ = app.emit("auth:error", auth::AuthCommandError::from(err));
+    }
+}
+
+/// The links among a second instance's command line arguments, which may be quoted.
+fn links_from_args(args: &[String]) -> Vec<Url> {
+    args.iter()
+        .filter_map(|raw| {
+            let candidate = raw.trim_matches('"').trim();
+            Url::parse(candidate)
+                .ok()
+                .filter(|url| url.scheme() == SCHEME)
+        })
+        .collect()
+}
+
+/// The same link can arrive twice in one batch (e.g. quoted and unquoted on Windows). Keeps the
+/// first occurrence of each url and only the first OAuth callback, since a second one would fail
+/// anyway once the pending login is consumed.
+fn unique_links(urls: impl IntoIterator<Item = Url>) -> Vec<Url> {
+    let mut seen = HashSet::new();
+    let mut has_callback = false;
+    urls.into_iter()
+        .filter(|url| {
+            if !seen.insert(url.to_string()) {
+                return false;
+            }
+            if is_oauth_callback(url) {
+                if has_callback {
+                    return false;
                 }
-                focus_main_window(&app_handle);
-            } else if let Some(route) = extract_route(&url) {
-                let state = app_handle.state::<DeepLinkState>();
-                state.set_route(Some(route.clone()));
-                let _ = app_handle.emit("app:navigate", route);
-                focus_main_window(&app_handle);
+                has_callback = true;
             }
-        }
-    });
+            true
+        })
+        .collect()
 }
 
 fn is_oauth_callback(url: &Url) -> bool {
     url.scheme() == SCHEME && url.host_str() == Some(CALLBACK_HOST) && url.path() == CALLBACK_PATH
 }
 
-fn extract_route(url: &Url) -> Option<String> {
+/// `vision://projects/42` and `vision:projects/42` both yield `["projects", "42"]`.
+fn route_segments(url: &Url) -> Option<Vec<&str>> {
     if url.scheme() != SCHEME {
         return None;
     }
 
+    let mut segments = Vec::new();
     let host = url.host_str().unwrap_or_default();
-    let path = url.path().trim_matches('/');
+    if !host.is_empty() && host != CALLBACK_HOST {
+        segments.push(host);
+    }
+    segments.extend(url.path().split('/').filter(|segment| !segment.is_empty()));
+    Some(segments)
+}
 
-    let route = if !host.is_empty() && host != CALLBACK_HOST {
-        host
-    } else {
-        path
-    };
+/// Matches `segments` against a pattern like `projects/:id`. Returns the route without its
+/// parameter segments (`projects`) and the captured values.
+fn match_route(pattern: &str, segments: &[&str]) -> Option<(String, HashMap<String, String>)> {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    if parts.len() != segments.len() {
+        return None;
+    }
 
-    match route {
-        "home"
-        | "projects"
-        | "news"
-        | "explore"
-        | "media"
-        | "settings"
-        | "settings-debug"
-        | "profile"
-        | "editor"
-        | "analytics"
-        | "calendar"
-        | "admin"
-        | "roles"
-        | "members" => Some(route.to_string()),
-        _ => None,
-    }
-}
-
-fn focus_main_window(app: &AppHandle) {
+    let mut route = Vec::new();
+    let mut captures = HashMap::new();
+    for (part, segment) in parts.iter().zip(segments) {
+        if let Some(name) = part.strip_prefix(':') {
+            if !is_valid_param(segment) {
+                return None;
+            }
+            captures.insert(name.to_string(), segment.to_string());
+        } else if part == segment {
+            route.push(*part);
+        } else {
+            return None;
+        }
+    }
+    Some((route.join("/"), captures))
+}
+
+/// Ids are handed to the frontend router, so only plain id/uuid characters are accepted.
+fn is_valid_param(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_PARAM_LEN
+        && value
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+}
+
+const fn build_config(value: Option<&'static str>, default: &'static str) -> &'static str {
+    match value {
+        Some(value) => value,
+        None => default,
+    }
+}
+
+fn navigate(app: &AppHandle, route: DeepLinkRoute) {
+    app.state::<DeepLinkState>().set_route(Some(route.clone()));
+    let _ = app.emit("app:navigate", route);
+}
+
+pub(crate) fn focus_main_window(app: &AppHandle) {
     if let Some(window) = app.get_webview_window("main") {
         let _ = window.show();
+        let _ = window.unminimize();
         let _ = window.set_focus();
     }
 }
 
 // Platform registration notes:
 // - Windows: requires protocol registration in the installer (MSI/NSIS).
-// - macOS: Info.plist CFBundleURLTypes must include the "vision" scheme.
-// - Linux: add a .desktop file with MimeType=x-scheme-handler/vision.
+// - macOS: Info.plist CFBundleURLTypes must include the scheme ("vision" by default).
+// - Linux: add a .desktop file with MimeType=x-scheme-handler/<scheme>.
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn callback(query: &str) -> String {
+        format!("{SCHEME}://{CALLBACK_HOST}{CALLBACK_PATH}?{query}")
+    }
+
+    fn route(path: &str) -> String {
+        format!("{SCHEME}://{path}")
+    }
+
+    /// Dispatches the links among `args` like a second instance's batch, returning the callbacks
+    /// handled and every link in the order it was handled.
+    fn dispatch(args: &[String]) -> (Vec<String>, Vec<String>) {
+        let callbacks = RefCell::new(Vec::new());
+        let handled = RefCell::new(Vec::new());
+        tauri::async_runtime::block_on(dispatch_links(
+            links_from_args(args),
+            |url| {
+                callbacks.borrow_mut().push(url.to_string());
+                handled.borrow_mut().push(url.to_string());
+                std::future::ready(())
+            },
+            |url| handled.borrow_mut().push(url.to_string()),
+        ));
+        (callbacks.into_inner(), handled.into_inner())
+    }
+
+    fn links(args: &[String]) -> Vec<String> {
+        dispatch(args).1
+    }
+
+    #[test]
+    fn quoted_and_unquoted_callback_is_handled_once() {
+        let link = callback("code=abc&state=xyz");
+        let args = vec![
+            "vision-desktop".to_string(),
+            format!("\"{link}\""),
+            link.clone(),
+        ];
+        assert_eq!(dispatch(&args).0, vec![link]);
+    }
+
+    #[test]
+    fn repeated_unquoted_callback_is_handled_once() {
+        let link = callback("code=abc&state=xyz");
+        assert_eq!(dispatch(&[link.clone(), link.clone()]).0, vec![link]);
+    }
+
+    #[test]
+    fn only_the_first_of_two_different_callbacks_is_handled() {
+        let first = callback("code=abc&state=xyz");
+        let second = callback("code=def&state=uvw");
+        assert_eq!(dispatch(&[first.clone(), second]).0, vec![first]);
+    }
+
+    #[test]
+    fn other_links_are_kept_in_order() {
+        let projects = route("projects/42");
+        let settings = route("settings");
+        let login = callback("code=abc&state=xyz");
+        let args = vec![
+            projects.clone(),
+            login.clone(),
+            settings.clone(),
+            projects.clone(),
+            login.clone(),
+        ];
+        assert_eq!(links(&args), vec![projects, login, settings]);
+    }
+
+    #[test]
+    fn default_routes_include_the_application_pages() {
+        let state = DeepLinkState::new();
+        for page in ["player-applications", "applications"] {
+            let url = Url::parse(&route(page)).unwrap();
+            assert_eq!(state.resolve_route(&url).route, page);
+        }
+    }
+}
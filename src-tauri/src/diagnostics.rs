@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::{auth, logging};
+
+const KEYRING_SERVICE: &str = "vision-desktop";
+const KEYRING_PROBE_ACCOUNT: &str = "diagnostics_probe";
+const KEYRING_PROBE_VALUE: &str = "probe";
+const BUNDLE_FILE_NAME: &str = "vision-diagnostics.zip";
+
+/// Whether the OS secret store can hold tokens. When it can't, `save_tokens` falls back to the
+/// app store, which settings surfaces as a warning.
+#[derive(Debug, Serialize)]
+pub struct KeyringStatus {
+    pub available: bool,
+    pub backend: &'static str,
+    pub error: Option<String>,
+}
+
+/// Round-trips a throwaway entry through the keyring. Slow on some platforms, so call it when the
+/// settings page opens rather than on every render.
+#[tauri::command]
+pub async fn diagnostics_check_keyring() -> KeyringStatus {
+    let error = tauri::async_runtime::spawn_blocking(probe_keyring)
+        .await
+        .unwrap_or_else(|err| Err(err.to_string()))
+        .err();
+
+    KeyringStatus {
+        available: error.is_none(),
+        backend: keyring_backend(),
+        error,
+    }
+}
+
+fn probe_keyring() -> Result<(), String> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_PROBE_ACCOUNT)
+        .map_err(|err| err.to_string())?;
+    entry
+        .set_password(KEYRING_PROBE_VALUE)
+        .map_err(|err| format!("write failed: {err}"))?;
+
+    let read = entry.get_password();
+    let _ = entry.delete_password();
+    match read {
+        Ok(value) if value == KEYRING_PROBE_VALUE => Ok(()),
+        Ok(_) => Err("read back a different value".into()),
+        Err(err) => Err(format!("read failed: {err}")),
+    }
+}
+
+fn keyring_backend() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "macOS Keychain"
+    } else if cfg!(target_os = "ios") {
+        "iOS Keychain"
+    } else if cfg!(target_os = "windows") {
+        "Windows Credential Manager"
+    } else if cfg!(any(
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    )) {
+        "Secret Service"
+    } else {
+        "unsupported"
+    }
+}
+
+/// Zips the current log file, the app info and the redacted auth summary for support requests.
+/// `destination` is the archive path, or a directory to create `vision-diagnostics.zip` in.
+/// Returns the archive path. Tokens and the client secret are never included.
+#[tauri::command]
+pub async fn diagnostics_export_bundle(
+    app: AppHandle,
+    destination: String,
+) -> Result<String, String> {
+    let app_info =
+        serde_json::to_vec_pretty(&crate::get_app_info()).map_err(|err| err.to_string())?;
+    let auth_summary = match auth::debug_state(&app, &app.state::<auth::AuthState>()) {
+        Ok(summary) => serde_json::to_vec_pretty(&summary),
+        Err(err) => serde_json::to_vec_pretty(&serde_json::json!({ "error": err.to_string() })),
+    }
+    .map_err(|err| err.to_string())?;
+    let log_path = logging::log_file_path(&app)?;
+
+    let mut archive_path = PathBuf::from(destination);
+    if archive_path.is_dir() {
+        archive_path.push(BUNDLE_FILE_NAME);
+    }
+
+    tauri::async_runtime::spawn_blocking(move || {
+        write_bundle(&archive_path, &app_info, &auth_summary, &log_path)?;
+        Ok(archive_path.to_string_lossy().into_owned())
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+fn write_bundle(
+    archive_path: &Path,
+    app_info: &[u8],
+    auth_summary: &[u8],
+    log_path: &Path,
+) -> Result<(), String> {
+    let file = File::create(archive_path).map_err(|err| format!("create failed: {err}"))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+
+    let mut add = |name: &str, contents: &[u8]| -> Result<(), String> {
+        zip.start_file(name, options)
+            .map_err(|err| format!("write {name} failed: {err}"))?;
+        zip.write_all(contents)
+            .map_err(|err| format!("write {name} failed: {err}"))
+    };
+    add("app-info.json", app_info)?;
+    add("auth.json", auth_summary)?;
+    // Nothing may have been logged yet on a fresh install.
+    if let Ok(log) = std::fs::read(log_path) {
+        add("vision-desktop.log", &log)?;
+    }
+
+    zip.finish()
+        .map_err(|err| format!("finish failed: {err}"))?;
+    Ok(())
+}
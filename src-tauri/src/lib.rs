@@ -1,35 +1,152 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
 use serde::{Deserialize, Serialize};
-use std::sync::{Mutex, OnceLock};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+use futures_util::stream::{self, StreamExt};
 use std::collections::HashMap;
-use tauri::Manager;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
+use tokio::io::AsyncWriteExt;
 
 mod auth;
 mod deeplink;
+mod diagnostics;
+mod logging;
+mod proxy;
+mod settings;
+#[cfg(desktop)]
+mod shortcut;
+#[cfg(all(desktop, feature = "tray"))]
+mod tray;
+mod websocket;
+mod window_state;
 
 #[derive(Serialize)]
 struct AppInfo {
     name: String,
     version: String,
+    os: &'static str,
+    arch: &'static str,
+    tauri_version: &'static str,
+    git_commit: Option<&'static str>,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct HttpRequest {
     method: String,
     url: String,
     headers: Option<HashMap<String, String>>,
+    query: Option<HashMap<String, String>>,
     body: Option<String>,
+    /// Sent as the body with `Content-Type: application/json` unless the caller set one.
+    /// Mutually exclusive with `body`.
+    json: Option<serde_json::Value>,
+    response_type: Option<HttpResponseType>,
+    timeout_ms: Option<u64>,
+    follow_redirects: Option<bool>,
+    request_id: Option<String>,
+    use_cookie_jar: Option<bool>,
+    retry: Option<HttpRetry>,
+    /// Largest response body accepted, `HTTP_DEFAULT_MAX_BYTES` when unset. Downloads that may
+    /// be bigger belong in `http_download`, which streams to disk.
+    max_bytes: Option<u64>,
+    /// Adds `Authorization: Bearer` with the signed-in account's access token, refreshed if
+    /// needed, so the token never reaches the frontend. Fails when nobody is signed in.
+    authenticated: Option<bool>,
+}
+
+/// Retries connection failures, timeouts and 5xx responses with exponential backoff
+/// (`baseDelayMs`, doubled after each attempt). Only GET/HEAD/PUT/DELETE are retried unless
+/// `allowNonIdempotent` is set.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HttpRetry {
+    max_attempts: u32,
+    base_delay_ms: u64,
+    allow_non_idempotent: Option<bool>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HttpResponseType {
+    #[default]
+    Text,
+    Bytes,
 }
 
+/// `body` holds the response text, or the raw bytes as standard base64 when the request asked for
+/// `responseType: "bytes"`. `encoding` says which one ("text" or "base64") so the frontend knows
+/// whether to decode. `finalUrl` is where the request ended up after following redirects.
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct HttpResponse {
     status: u16,
+    headers: HashMap<String, String>,
     body: String,
+    encoding: &'static str,
+    final_url: String,
+}
+
+/// Outcome of `net_probe`. `status` is set whenever the server answered, even with an error
+/// status; otherwise `failure` says which stage failed.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProbeResult {
+    status: Option<u16>,
+    elapsed_ms: u64,
+    failure: Option<ProbeFailure>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProbeFailure {
+    Dns,
+    Connect,
+    Timeout,
+    Other,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
+struct HttpDownloadRequest {
+    url: String,
+    headers: Option<HashMap<String, String>>,
+    destination: String,
+    /// Hex digest the file must match, i.e. of the bytes as published; on a mismatch the file is
+    /// deleted and the call fails.
+    expected_sha256: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HttpDownloadResult {
+    status: u16,
+    bytes: u64,
+    /// Lowercase hex SHA-256 of the downloaded file, as written to disk.
+    sha256: String,
+}
+
+#[derive(Clone, Serialize)]
+struct HttpProgress {
+    url: String,
+    destination: String,
+    downloaded: u64,
+    total: Option<u64>,
+}
+
+/// Image keys must name art assets uploaded to the Discord application (or be an image url).
+/// Discord accepts unknown keys without complaint and just renders no image; the keys sent are
+/// logged at debug level to compare against the uploaded asset names.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct DiscordPresencePayload {
+    details: Option<String>,
     state: Option<String>,
     start_timestamp: Option<i64>,
     end_timestamp: Option<i64>,
@@ -38,15 +155,262 @@ struct DiscordPresencePayload {
     small_image_key: Option<String>,
     small_image_text: Option<String>,
     party_id: Option<String>,
+    /// `[current, max]`, shown as e.g. "3 of 5".
+    party_size: Option<[u32; 2]>,
     join_secret: Option<String>,
+    spectate_secret: Option<String>,
+    buttons: Option<Vec<DiscordButton>>,
+    activity_type: Option<String>,
+}
+
+/// The Discord account the IPC connection belongs to, taken from the handshake's READY payload.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+struct DiscordUser {
+    id: String,
+    username: String,
+    global_name: Option<String>,
+    avatar: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+struct DiscordButton {
+    label: String,
+    url: String,
 }
 
+const HTTP_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const HTTP_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const HTTP_MAX_REDIRECTS: usize = 10;
+const HTTP_MAX_RETRY_ATTEMPTS: u32 = 10;
+const HTTP_DEFAULT_MAX_BYTES: u64 = 50 * 1024 * 1024;
+const HTTP_BATCH_CONCURRENCY: usize = 6;
+const DISCORD_MAX_BUTTONS: usize = 2;
+const DISCORD_MAX_ASSET_KEY_LEN: usize = 256;
+const DISCORD_OPCODE_HANDSHAKE: u8 = 0;
+const DISCORD_UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+const DISCORD_AUTOCONNECT_INITIAL_DELAY: Duration = Duration::from_secs(2);
+const DISCORD_AUTOCONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const DISCORD_RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const DISCORD_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+static HTTP_CLIENTS: OnceLock<Mutex<HashMap<HttpClientOptions, reqwest::Client>>> =
+    OnceLock::new();
+static HTTP_ALLOWED_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+static HTTP_COOKIE_JAR: OnceLock<Mutex<Arc<reqwest::cookie::Jar>>> = OnceLock::new();
+static HTTP_IN_FLIGHT: OnceLock<Mutex<HashMap<String, tokio::task::AbortHandle>>> =
+    OnceLock::new();
 static DISCORD_CLIENT: OnceLock<Mutex<Option<DiscordIpcClient>>> = OnceLock::new();
+static DISCORD_THROTTLE: OnceLock<Mutex<DiscordThrottle>> = OnceLock::new();
+static DISCORD_IDLE: OnceLock<Mutex<DiscordIdle>> = OnceLock::new();
+static DISCORD_BACKOFF: OnceLock<Mutex<DiscordBackoff>> = OnceLock::new();
+/// Set in `setup`, so the Discord helpers can emit `discord:error` and `discord:disconnected`.
+static DISCORD_APP: OnceLock<AppHandle> = OnceLock::new();
+/// The presence last sent to Discord, kept for partial updates. Only stored once the send
+/// succeeded, so a failed update doesn't count as shown. Cleared together with the activity.
+static DISCORD_LAST_PRESENCE: OnceLock<Mutex<Option<(String, DiscordPresencePayload)>>> =
+    OnceLock::new();
+/// User from the last successful handshake; only meaningful while `DISCORD_CLIENT` holds a client.
+static DISCORD_USER: OnceLock<Mutex<Option<DiscordUser>>> = OnceLock::new();
+/// Bumped to stop a running autoconnect loop; each loop only runs while its generation is current.
+static DISCORD_AUTOCONNECT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Discord only accepts a presence update roughly every 15 seconds. Updates arriving faster are
+/// parked in `pending` (newest wins) and flushed once the interval has passed. Only the flush
+/// scheduled for the current `generation` may send; dropping the parked update bumps it.
+/// A parked update: app id, the payload it was built from and the activity to send.
+type PendingPresence = (String, DiscordPresencePayload, activity::Activity<'static>);
+
+#[derive(Default)]
+struct DiscordThrottle {
+    last_update: Option<Instant>,
+    pending: Option<PendingPresence>,
+    generation: u64,
+}
+
+impl DiscordThrottle {
+    /// How long an update sent now would have to wait, or `None` if it may go out right away.
+    fn wait(&self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.last_update?);
+        DISCORD_UPDATE_INTERVAL
+            .checked_sub(elapsed)
+            .filter(|wait| !wait.is_zero())
+    }
+
+    fn drop_pending(&mut self) {
+        self.pending = None;
+        self.generation += 1;
+    }
+}
+
+/// Spaces out connect attempts after failures so a missing Discord isn't hammered: the first
+/// retry waits `base`, each further failure doubles the wait up to `max`. A successful connect
+/// resets it.
+struct DiscordBackoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl DiscordBackoff {
+    fn reset(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+impl Default for DiscordBackoff {
+    fn default() -> Self {
+        Self {
+            base: DISCORD_RECONNECT_BASE_DELAY,
+            max: DISCORD_RECONNECT_MAX_DELAY,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+}
+
+/// Clears the presence once no `discord_update_presence` arrived for `timeout`. A single watcher
+/// thread runs while a timeout is set and an activity is showing; `last_activity` is `None` once
+/// the presence was cleared, so the next update starts watching again.
+#[derive(Default)]
+struct DiscordIdle {
+    timeout: Option<Duration>,
+    last_activity: Option<Instant>,
+    watching: bool,
+}
+
+/// Settings that can only be applied when building a `reqwest::Client`. One client is kept per
+/// combination so requests sharing the same settings share a connection pool.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct HttpClientOptions {
+    follow_redirects: bool,
+    use_cookie_jar: bool,
+    /// Sends `Accept-Encoding: gzip, br` and decodes compressed bodies. Off when the caller set
+    /// `Accept-Encoding` itself, so it gets the body exactly as the server encoded it, and for
+    /// downloads, which must match the published file byte for byte.
+    decompress: bool,
+}
+
+/// Shared across `http_request` calls so connections and TLS sessions are pooled. Timeouts are
+/// applied per request rather than on the client.
+fn http_client(options: HttpClientOptions) -> Result<reqwest::Client, String> {
+    let mut clients = HTTP_CLIENTS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|_| "http client lock failed")?;
+
+    if let Some(client) = clients.get(&options) {
+        return Ok(client.clone());
+    }
+
+    let redirect_policy = if options.follow_redirects {
+        // Redirect targets go through the same host checks as the original url.
+        reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= HTTP_MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if let Err(error) = check_http_host(attempt.url()) {
+                attempt.error(error)
+            } else {
+                attempt.follow()
+            }
+        })
+    } else {
+        reqwest::redirect::Policy::none()
+    };
+    let mut builder = proxy::apply(
+        reqwest::Client::builder()
+            .redirect(redirect_policy)
+            .gzip(options.decompress)
+            .brotli(options.decompress),
+    )?;
+    if options.use_cookie_jar {
+        let jar = http_cookie_jar()
+            .lock()
+            .map_err(|_| "cookie jar lock failed")?
+            .clone();
+        builder = builder.cookie_provider(jar);
+    }
+    let client = builder
+        .build()
+        .map_err(|error| format!("client build failed: {error}"))?;
+
+    clients.insert(options, client.clone());
+    Ok(client)
+}
+
+/// Hosts that `http_request` and `http_download` may reach, configured as a comma-separated list
+/// in `VISION_HTTP_ALLOWED_HOSTS`. The variable is read at runtime first and falls back to its
+/// value at build time, so integrators can either bake a list into a build or set it per machine.
+///
+/// Entries are exact host names or addresses (`api.example.com`, `127.0.0.1`, `::1`) or subdomain
+/// wildcards (`*.example.com`); a bare `*` matches any host. An empty or unset list allows any
+/// public host.
+///
+/// Loopback, link-local, private and multicast addresses, including NAT64 and 6to4 addresses
+/// leading to them, are refused unless the host is listed exactly; wildcards never unlock them. For local development use e.g. `*,localhost,127.0.0.1`.
+fn http_allowed_hosts() -> &'static [String] {
+    HTTP_ALLOWED_HOSTS.get_or_init(|| {
+        std::env::var("VISION_HTTP_ALLOWED_HOSTS")
+            .ok()
+            .or_else(|| option_env!("VISION_HTTP_ALLOWED_HOSTS").map(str::to_string))
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|entry| entry.trim().to_ascii_lowercase())
+                    .filter(|entry| !entry.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// Cookies shared by every request that opts in with `useCookieJar`, kept for the session.
+fn http_cookie_jar() -> &'static Mutex<Arc<reqwest::cookie::Jar>> {
+    HTTP_COOKIE_JAR.get_or_init(|| Mutex::new(Arc::new(reqwest::cookie::Jar::default())))
+}
+
+fn http_in_flight() -> &'static Mutex<HashMap<String, tokio::task::AbortHandle>> {
+    HTTP_IN_FLIGHT.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
 fn discord_client() -> &'static Mutex<Option<DiscordIpcClient>> {
     DISCORD_CLIENT.get_or_init(|| Mutex::new(None))
 }
 
+fn discord_throttle() -> &'static Mutex<DiscordThrottle> {
+    DISCORD_THROTTLE.get_or_init(|| Mutex::new(DiscordThrottle::default()))
+}
+
+fn discord_idle() -> &'static Mutex<DiscordIdle> {
+    DISCORD_IDLE.get_or_init(|| Mutex::new(DiscordIdle::default()))
+}
+
+fn discord_backoff() -> &'static Mutex<DiscordBackoff> {
+    DISCORD_BACKOFF.get_or_init(|| Mutex::new(DiscordBackoff::default()))
+}
+
+fn emit_discord_error(message: &str) {
+    if let Some(app) = DISCORD_APP.get() {
+        let _ = app.emit("discord:error", message);
+    }
+}
+
+fn emit_discord_disconnected() {
+    if let Some(app) = DISCORD_APP.get() {
+        let _ = app.emit("discord:disconnected", ());
+    }
+}
+
+fn discord_last_presence() -> &'static Mutex<Option<(String, DiscordPresencePayload)>> {
+    DISCORD_LAST_PRESENCE.get_or_init(|| Mutex::new(None))
+}
+
+fn discord_user() -> &'static Mutex<Option<DiscordUser>> {
+    DISCORD_USER.get_or_init(|| Mutex::new(None))
+}
+
 fn normalize_timestamp(value: i64) -> i64 {
     if value < 1_000_000_000_000 {
         value * 1000
@@ -55,27 +419,287 @@ fn normalize_timestamp(value: i64) -> i64 {
     }
 }
 
+/// Replaces the whole activity: fields missing from `presence` disappear from Discord. Use
+/// `discord_update_timestamps` to change only the timer.
 #[tauri::command]
 fn discord_update_presence(app_id: String, presence: DiscordPresencePayload) -> Result<(), String> {
+    let activity = build_activity(&presence)?;
+    log::debug!(
+        target: logging::DISCORD_PRESENCE_TARGET,
+        "discord presence assets: large={:?} small={:?}",
+        presence.large_image_key,
+        presence.small_image_key
+    );
+    note_presence_activity();
+
+    // Held until the send is stamped, so a deferred flush can't slip in between the decision
+    // and `last_update`.
+    let mut throttle = discord_throttle()
+        .lock()
+        .map_err(|_| "discord throttle lock failed")?;
+    if let Some(wait) = throttle.wait(Instant::now()) {
+        let flush_scheduled = throttle.pending.is_some();
+        throttle.pending = Some((app_id, presence, activity));
+        if !flush_scheduled {
+            let generation = throttle.generation;
+            tauri::async_runtime::spawn(async move {
+                tokio::time::sleep(wait).await;
+                let _ = tauri::async_runtime::spawn_blocking(move || {
+                    flush_pending_presence(generation)
+                })
+                .await;
+            });
+        }
+        return Ok(());
+    }
+    // A flush that is due but hasn't run yet would send an older presence over this one.
+    throttle.drop_pending();
+
     let mut guard = discord_client()
         .lock()
         .map_err(|_| "discord client lock failed")?;
 
-    if guard.is_none() {
-        let mut client = DiscordIpcClient::new(&app_id);
-        client
-            .connect()
-            .map_err(|error| format!("discord connect failed: {error}"))?;
-        *guard = Some(client);
+    send_activity(&mut guard, &app_id, activity)?;
+    throttle.last_update = Some(Instant::now());
+    remember_presence(app_id, presence);
+    Ok(())
+}
+
+fn remember_presence(app_id: String, presence: DiscordPresencePayload) {
+    if let Ok(mut last) = discord_last_presence().lock() {
+        *last = Some((app_id, presence));
+    }
+}
+
+/// Sets the timestamps of the current presence and resends it. Unlike `discord_update_presence`
+/// this merges: every other field keeps its last value. A `None` removes that timestamp, e.g. to
+/// reset the elapsed timer pass only a new `start`.
+#[tauri::command]
+fn discord_update_timestamps(start: Option<i64>, end: Option<i64>) -> Result<(), String> {
+    // A parked update is newer than the presence last sent, so merge into that one.
+    let parked = discord_throttle()
+        .lock()
+        .map_err(|_| "discord throttle lock failed")?
+        .pending
+        .as_ref()
+        .map(|(app_id, presence, _)| (app_id.clone(), presence.clone()));
+    let (app_id, mut presence) = match parked {
+        Some(parked) => parked,
+        None => discord_last_presence()
+            .lock()
+            .map_err(|_| "discord presence lock failed")?
+            .clone()
+            .ok_or("no discord presence to update")?,
+    };
+
+    presence.start_timestamp = start;
+    presence.end_timestamp = end;
+    discord_update_presence(app_id, presence)
+}
+
+fn flush_pending_presence(generation: u64) {
+    let Ok(mut throttle) = discord_throttle().lock() else {
+        return;
+    };
+    if throttle.generation != generation {
+        return;
+    }
+    let Some((app_id, presence, activity)) = throttle.pending.take() else {
+        return;
+    };
+
+    let Ok(mut guard) = discord_client().lock() else {
+        return;
+    };
+
+    match send_activity(&mut guard, &app_id, activity) {
+        Ok(()) => {
+            throttle.last_update = Some(Instant::now());
+            remember_presence(app_id, presence);
+        }
+        Err(error) => log::warn!("deferred discord update failed: {error}"),
+    }
+}
+
+/// Clears the presence after `secs` without a `discord_update_presence`; the next update shows
+/// it again. `None` keeps the presence up indefinitely.
+#[tauri::command]
+fn discord_set_idle_timeout(secs: Option<u64>) -> Result<(), String> {
+    let mut idle = discord_idle()
+        .lock()
+        .map_err(|_| "discord idle lock failed")?;
+    idle.timeout = secs.map(Duration::from_secs);
+    ensure_idle_watcher(&mut idle);
+    Ok(())
+}
+
+fn note_presence_activity() {
+    if let Ok(mut idle) = discord_idle().lock() {
+        idle.last_activity = Some(Instant::now());
+        ensure_idle_watcher(&mut idle);
+    }
+}
+
+fn ensure_idle_watcher(idle: &mut DiscordIdle) {
+    if idle.watching || idle.timeout.is_none() || idle.last_activity.is_none() {
+        return;
+    }
+    idle.watching = true;
+    std::thread::spawn(watch_presence_idle);
+}
+
+fn watch_presence_idle() {
+    loop {
+        let wait = {
+            let Ok(mut idle) = discord_idle().lock() else {
+                return;
+            };
+            let (Some(timeout), Some(last_activity)) = (idle.timeout, idle.last_activity) else {
+                idle.watching = false;
+                return;
+            };
+            let elapsed = last_activity.elapsed();
+            if elapsed >= timeout {
+                // Cleared under the idle lock so an update arriving right now can't be wiped.
+                idle.watching = false;
+                idle.last_activity = None;
+                if let Err(error) = clear_discord_activity() {
+                    log::warn!("discord idle clear failed: {error}");
+                }
+                return;
+            }
+            timeout - elapsed
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+/// Connects unless a recent failure is still backing off. Failures are reported as
+/// `discord:error` besides being returned.
+fn connect_discord(app_id: &str) -> Result<DiscordIpcClient, String> {
+    let mut backoff = discord_backoff()
+        .lock()
+        .map_err(|_| "discord backoff lock failed")?;
+    if let Some(wait) = backoff
+        .retry_at
+        .and_then(|retry_at| retry_at.checked_duration_since(Instant::now()))
+    {
+        return Err(format!(
+            "discord connect failed recently; retrying in {}s",
+            wait.as_secs() + 1
+        ));
+    }
+
+    match handshake_discord(app_id) {
+        Ok(client) => {
+            backoff.reset();
+            Ok(client)
+        }
+        Err(error) => {
+            let delay = backoff
+                .base
+                .saturating_mul(2u32.saturating_pow(backoff.failures))
+                .min(backoff.max);
+            backoff.failures = backoff.failures.saturating_add(1);
+            backoff.retry_at = Some(Instant::now() + delay);
+            drop(backoff);
+            emit_discord_error(&error);
+            Err(error)
+        }
+    }
+}
+
+/// Connects and performs the handshake by hand rather than through `connect()`, which throws
+/// away Discord's READY reply and with it the connected user.
+fn handshake_discord(app_id: &str) -> Result<DiscordIpcClient, String> {
+    let mut client = DiscordIpcClient::new(app_id);
+    client
+        .connect_ipc()
+        .map_err(|error| format!("discord connect failed: {error}"))?;
+    let (_, ready) = client
+        .send(
+            serde_json::json!({ "v": 1, "client_id": app_id }),
+            DISCORD_OPCODE_HANDSHAKE,
+        )
+        .and_then(|_| client.recv())
+        .map_err(|error| format!("discord handshake failed: {error}"))?;
+
+    if ready.get("evt").and_then(|evt| evt.as_str()) != Some("READY") {
+        let message = ready
+            .get("message")
+            .and_then(|message| message.as_str())
+            .unwrap_or("unexpected reply");
+        return Err(format!("discord handshake rejected: {message}"));
+    }
+
+    let user = ready
+        .pointer("/data/user")
+        .cloned()
+        .and_then(|user| serde_json::from_value::<DiscordUser>(user).ok());
+    if let Ok(mut current) = discord_user().lock() {
+        *current = user;
+    }
+    Ok(client)
+}
+
+/// Sends the activity over the stored client, connecting first if needed. A failed send usually
+/// means Discord was restarted underneath us, so the stale client is replaced by a fresh
+/// connection and the send is retried once.
+fn send_activity(
+    slot: &mut Option<DiscordIpcClient>,
+    app_id: &str,
+    activity: activity::Activity<'static>,
+) -> Result<(), String> {
+    if slot.is_none() {
+        *slot = Some(connect_discord(app_id)?);
     }
 
-    let client = guard
+    let client = slot
         .as_mut()
         .ok_or_else(|| "discord client missing".to_string())?;
 
+    let Err(error) = client.set_activity(activity.clone()) else {
+        return Ok(());
+    };
+
+    log::warn!("discord update failed, reconnecting: {error}");
+    *slot = None;
+    emit_discord_disconnected();
+
+    let mut client = connect_discord(app_id)?;
+    if let Err(error) = client.set_activity(activity) {
+        let error = format!("discord update failed: {error}");
+        emit_discord_error(&error);
+        return Err(error);
+    }
+    *slot = Some(client);
+
+    Ok(())
+}
+
+fn build_activity(presence: &DiscordPresencePayload) -> Result<activity::Activity<'static>, String> {
+    if let Some(buttons) = presence.buttons.as_deref() {
+        validate_discord_buttons(buttons)?;
+    }
+    if let Some(key) = presence.large_image_key.as_deref() {
+        validate_discord_asset_key("largeImageKey", key)?;
+    }
+    if let Some(key) = presence.small_image_key.as_deref() {
+        validate_discord_asset_key("smallImageKey", key)?;
+    }
+    let party_size = presence.party_size.map(discord_party_size).transpose()?;
+
     let mut activity = activity::Activity::new();
 
-    if let Some(state) = presence.state.as_deref() {
+    if let Some(activity_type) = presence.activity_type.as_deref() {
+        activity = activity.activity_type(parse_activity_type(activity_type)?);
+    }
+
+    if let Some(details) = presence.details.clone() {
+        activity = activity.details(details);
+    }
+
+    if let Some(state) = presence.state.clone() {
         activity = activity.state(state);
     }
 
@@ -98,53 +722,277 @@ fn discord_update_presence(app_id: String, presence: DiscordPresencePayload) ->
         let mut assets = activity::Assets::new();
         let large_key = presence
             .large_image_key
-            .as_deref()
-            .unwrap_or("launcher_icon");
+            .clone()
+            .unwrap_or_else(|| "launcher_icon".to_string());
         assets = assets.large_image(large_key);
-        if let Some(large_text) = presence.large_image_text.as_deref() {
+        if let Some(large_text) = presence.large_image_text.clone() {
             assets = assets.large_text(large_text);
         }
-        if let Some(small_key) = presence.small_image_key.as_deref() {
+        if let Some(small_key) = presence.small_image_key.clone() {
             assets = assets.small_image(small_key);
         }
-        if let Some(small_text) = presence.small_image_text.as_deref() {
+        if let Some(small_text) = presence.small_image_text.clone() {
             assets = assets.small_text(small_text);
         }
         activity = activity.assets(assets);
     }
 
-    if let Some(party_id) = presence.party_id.as_deref() {
-        let party = activity::Party::new().id(party_id);
+    if presence.party_id.is_some() || party_size.is_some() {
+        let mut party = activity::Party::new();
+        if let Some(party_id) = presence.party_id.clone() {
+            party = party.id(party_id);
+        }
+        if let Some(size) = party_size {
+            party = party.size(size);
+        }
         activity = activity.party(party);
     }
 
-    if let Some(join_secret) = presence.join_secret.as_deref() {
-        let secrets = activity::Secrets::new().join(join_secret);
+    if presence.join_secret.is_some() || presence.spectate_secret.is_some() {
+        let mut secrets = activity::Secrets::new();
+        if let Some(join_secret) = presence.join_secret.clone() {
+            secrets = secrets.join(join_secret);
+        }
+        if let Some(spectate_secret) = presence.spectate_secret.clone() {
+            secrets = secrets.spectate(spectate_secret);
+        }
         activity = activity.secrets(secrets);
     }
 
-    client
-        .set_activity(activity)
-        .map_err(|error| format!("discord update failed: {error}"))?;
+    if let Some(buttons) = presence.buttons.as_deref() {
+        let buttons = buttons
+            .iter()
+            .map(|button| activity::Button::new(button.label.clone(), button.url.clone()))
+            .collect();
+        activity = activity.buttons(buttons);
+    }
+
+    Ok(activity)
+}
+
+fn parse_activity_type(value: &str) -> Result<activity::ActivityType, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "playing" => Ok(activity::ActivityType::Playing),
+        "listening" => Ok(activity::ActivityType::Listening),
+        "watching" => Ok(activity::ActivityType::Watching),
+        "competing" => Ok(activity::ActivityType::Competing),
+        _ => Err(format!("unknown discord activity type: {value}")),
+    }
+}
+
+fn discord_party_size([current, max]: [u32; 2]) -> Result<[i32; 2], String> {
+    if current > max {
+        return Err(format!(
+            "discord partySize current ({current}) must not exceed max ({max})"
+        ));
+    }
+    let max =
+        i32::try_from(max).map_err(|_| format!("discord partySize max {max} is too large"))?;
+    Ok([current as i32, max])
+}
+
+fn validate_discord_asset_key(field: &str, key: &str) -> Result<(), String> {
+    if key.trim().is_empty() {
+        return Err(format!("discord {field} must not be empty"));
+    }
+    if key.len() > DISCORD_MAX_ASSET_KEY_LEN {
+        return Err(format!(
+            "discord {field} is longer than {DISCORD_MAX_ASSET_KEY_LEN} characters"
+        ));
+    }
+    Ok(())
+}
+
+fn validate_discord_buttons(buttons: &[DiscordButton]) -> Result<(), String> {
+    if buttons.len() > DISCORD_MAX_BUTTONS {
+        return Err(format!(
+            "discord supports at most {DISCORD_MAX_BUTTONS} buttons, got {}",
+            buttons.len()
+        ));
+    }
+
+    for button in buttons {
+        let url = url::Url::parse(&button.url)
+            .map_err(|error| format!("invalid discord button url {:?}: {error}", button.url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!(
+                "discord button url must be http or https: {:?}",
+                button.url
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets how long failed connects back off: `baseMs` after the first failure, doubling up to
+/// `maxMs`. A `baseMs` of 0 retries immediately.
+#[tauri::command]
+fn discord_set_reconnect_backoff(base_ms: u64, max_ms: u64) -> Result<(), String> {
+    if base_ms > max_ms {
+        return Err("baseMs must not exceed maxMs".to_string());
+    }
+    let mut backoff = discord_backoff()
+        .lock()
+        .map_err(|_| "discord backoff lock failed")?;
+    backoff.base = Duration::from_millis(base_ms);
+    backoff.max = Duration::from_millis(max_ms);
+    backoff.retry_at = None;
+    Ok(())
+}
+
+/// Opens the IPC connection without setting an activity, so the app can connect at startup and
+/// send the first presence later. Does nothing when a client is already stored.
+#[tauri::command]
+fn discord_connect(app_id: String) -> Result<(), String> {
+    let mut guard = discord_client()
+        .lock()
+        .map_err(|_| "discord client lock failed")?;
 
+    if guard.is_none() {
+        *guard = Some(connect_discord(&app_id)?);
+    }
+    Ok(())
+}
+
+/// Keeps trying to connect in the background, backing off up to a minute between attempts, until
+/// Discord is running. Emits `discord:connected` once the connection is up; failed attempts are
+/// only logged, not reported as `discord:error`. A later call restarts the loop;
+/// `discord_disconnect` stops it.
+#[tauri::command]
+fn discord_enable_autoconnect(app: AppHandle, app_id: String) {
+    let generation = DISCORD_AUTOCONNECT_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    std::thread::spawn(move || {
+        let mut delay = DISCORD_AUTOCONNECT_INITIAL_DELAY;
+        while DISCORD_AUTOCONNECT_GENERATION.load(Ordering::SeqCst) == generation {
+            match try_autoconnect(&app_id) {
+                Ok(()) => {
+                    let _ = app.emit("discord:connected", ());
+                    return;
+                }
+                Err(error) => log::debug!("discord autoconnect attempt failed: {error}"),
+            }
+            std::thread::sleep(delay);
+            delay = (delay * 2).min(DISCORD_AUTOCONNECT_MAX_DELAY);
+        }
+    });
+}
+
+/// One autoconnect attempt. Skips `connect_discord`: the loop spaces out its own attempts, so the
+/// shared backoff would only stretch them further, and Discord not running yet is no error.
+fn try_autoconnect(app_id: &str) -> Result<(), String> {
+    let mut guard = discord_client()
+        .lock()
+        .map_err(|_| "discord client lock failed")?;
+    if guard.is_none() {
+        let client = handshake_discord(app_id)?;
+        if let Ok(mut backoff) = discord_backoff().lock() {
+            backoff.reset();
+        }
+        *guard = Some(client);
+    }
     Ok(())
 }
 
 #[tauri::command]
 fn discord_clear_presence() -> Result<(), String> {
+    if let Ok(mut idle) = discord_idle().lock() {
+        idle.last_activity = None;
+    }
+    clear_discord_activity()
+}
+
+fn clear_discord_activity() -> Result<(), String> {
+    // A parked update must not resurrect the presence after it was cleared.
+    if let Ok(mut throttle) = discord_throttle().lock() {
+        throttle.drop_pending();
+    }
+    if let Ok(mut last) = discord_last_presence().lock() {
+        *last = None;
+    }
+
     let mut guard = discord_client()
         .lock()
         .map_err(|_| "discord client lock failed")?;
 
     if let Some(client) = guard.as_mut() {
-        client
-            .clear_activity()
-            .map_err(|error| format!("discord clear failed: {error}"))?;
+        if let Err(error) = client.clear_activity() {
+            // Discord drops the activity together with a dead pipe, so there is nothing left to
+            // clear. Forget the client so the next update reconnects.
+            log::warn!("discord clear failed, dropping client: {error}");
+            *guard = None;
+            emit_discord_disconnected();
+        }
+    }
+
+    Ok(())
+}
+
+/// Closes the IPC connection, e.g. when the user turns the Discord integration off. Autoconnect
+/// stops and a parked update is dropped so neither can reconnect; the next
+/// `discord_update_presence` connects again.
+#[tauri::command]
+fn discord_disconnect() -> Result<(), String> {
+    DISCORD_AUTOCONNECT_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut throttle) = discord_throttle().lock() {
+        throttle.drop_pending();
+    }
+    if let Ok(mut last) = discord_last_presence().lock() {
+        *last = None;
+    }
+    if let Ok(mut idle) = discord_idle().lock() {
+        idle.last_activity = None;
     }
 
+    let mut guard = discord_client()
+        .lock()
+        .map_err(|_| "discord client lock failed")?;
+
+    if let Some(mut client) = guard.take() {
+        if let Err(error) = client.close() {
+            // The pipe is gone either way; nothing else to release.
+            log::warn!("discord close failed: {error}");
+        }
+    }
     Ok(())
 }
 
+/// Reports whether Discord was reachable at the last exchange over the IPC pipe. A failed send
+/// drops the client, so a Discord that quit shows as disconnected from the next update on.
+/// Nothing is sent from here: Discord answers every frame, and replies nobody reads would pile
+/// up in the pipe, while waiting for one could hang on a stalled Discord.
+#[tauri::command]
+fn discord_is_connected() -> bool {
+    discord_client()
+        .lock()
+        .map(|guard| guard.is_some())
+        .unwrap_or(false)
+}
+
+/// The Discord account presence is shown on, so the UI can name it and spot a wrong account.
+/// `None` while not connected.
+#[tauri::command]
+fn discord_get_user() -> Option<DiscordUser> {
+    let guard = discord_client().lock().ok()?;
+    guard.as_ref()?;
+    discord_user().lock().ok()?.clone()
+}
+
+/// Closes the Discord pipe on exit so the presence disappears immediately instead of lingering
+/// until Discord notices the process is gone. Uses `try_lock` so a connect that is still in
+/// flight can never hold up shutdown.
+fn discord_shutdown() {
+    let Ok(mut guard) = discord_client().try_lock() else {
+        return;
+    };
+
+    if let Some(mut client) = guard.take() {
+        if let Err(error) = client.close() {
+            log::warn!("discord close failed: {error}");
+        }
+    }
+}
+
 #[tauri::command]
 fn get_app_info() -> AppInfo {
     let config_version = serde_json::from_str::<serde_json::Value>(include_str!("../tauri.conf.json"))
@@ -154,85 +1002,930 @@ fn get_app_info() -> AppInfo {
     AppInfo {
         name: "Vision Desktop".to_string(),
         version: config_version.unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string()),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        tauri_version: tauri::VERSION,
+        git_commit: option_env!("VISION_GIT_COMMIT"),
+    }
+}
+
+/// Opens an http(s) URL in the default browser. Other schemes (`file://`, custom protocol
+/// handlers) are refused so web content can't launch local files or apps.
+#[tauri::command]
+fn open_external(app: AppHandle, url: String) -> Result<(), String> {
+    let parsed = url::Url::parse(&url).map_err(|_| format!("invalid url: {url}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        log::warn!("Refused to open external url with scheme {}", parsed.scheme());
+        return Err(format!("unsupported url scheme: {}", parsed.scheme()));
+    }
+
+    app.opener()
+        .open_url(parsed.as_str(), None::<&str>)
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn http_request(app: AppHandle, mut request: HttpRequest) -> Result<HttpResponse, String> {
+    if request.authenticated.unwrap_or(false) {
+        let token = auth::bearer_token(&app)
+            .await
+            .map_err(|err| format!("authenticated request failed: {err}"))?;
+        let headers = request.headers.get_or_insert_with(HashMap::new);
+        headers.retain(|key, _| !key.eq_ignore_ascii_case("authorization"));
+        headers.insert("Authorization".to_string(), format!("Bearer {token}"));
+    }
+
+    match request.request_id.clone() {
+        Some(request_id) => send_tracked_request(request_id, request).await,
+        None => send_http_request(request).await,
+    }
+}
+
+/// Runs the request as its own task, registered under `request_id` so `http_abort` can cancel
+/// it. An id that is still in flight is rejected rather than taking over the earlier request's
+/// entry.
+async fn send_tracked_request(
+    request_id: String,
+    request: HttpRequest,
+) -> Result<HttpResponse, String> {
+    let task = {
+        let mut in_flight = http_in_flight()
+            .lock()
+            .map_err(|_| "http in-flight lock failed")?;
+        if in_flight.contains_key(&request_id) {
+            return Err(format!("request id {request_id:?} is already in flight"));
+        }
+        let task = tokio::spawn(send_http_request(request));
+        in_flight.insert(request_id.clone(), task.abort_handle());
+        task
+    };
+    let task_id = task.id();
+
+    let result = task.await;
+
+    if let Ok(mut in_flight) = http_in_flight().lock() {
+        // After an `http_abort` the id may already belong to a newer request.
+        if in_flight
+            .get(&request_id)
+            .is_some_and(|handle| handle.id() == task_id)
+        {
+            in_flight.remove(&request_id);
+        }
+    }
+
+    match result {
+        Ok(response) => response,
+        Err(error) if error.is_cancelled() => Err("request aborted".to_string()),
+        Err(error) => Err(format!("request failed: {error}")),
     }
 }
 
+/// Runs several `http_request`s concurrently, at most `HTTP_BATCH_CONCURRENCY` at a time, and
+/// returns their results in input order. Each request keeps its own timeout, retry and
+/// `requestId`, and one failing doesn't affect the others.
+#[tauri::command]
+async fn http_request_batch(
+    app: AppHandle,
+    requests: Vec<HttpRequest>,
+) -> Vec<Result<HttpResponse, String>> {
+    let mut completed: Vec<(usize, Result<HttpResponse, String>)> =
+        stream::iter(requests.into_iter().enumerate())
+            .map(|(index, request)| {
+                let app = app.clone();
+                async move { (index, http_request(app, request).await) }
+            })
+            .buffer_unordered(HTTP_BATCH_CONCURRENCY)
+            .collect()
+            .await;
+
+    completed.sort_unstable_by_key(|(index, _)| *index);
+    completed.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Cancels an in-flight `http_request` started with the given `requestId`. Returns whether a
+/// matching request was still running.
+#[tauri::command]
+fn http_abort(request_id: String) -> bool {
+    let handle = http_in_flight()
+        .lock()
+        .ok()
+        .and_then(|mut in_flight| in_flight.remove(&request_id));
+
+    match handle {
+        Some(handle) => {
+            handle.abort();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Forgets all cookies collected through `useCookieJar`. `Jar` cannot be emptied in place, so a
+/// fresh one is installed and the clients holding the old jar are dropped from the cache.
+#[tauri::command]
+fn http_clear_cookies() -> Result<(), String> {
+    // Same lock order as `http_client`: clients first, then the jar.
+    let mut clients = HTTP_CLIENTS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|_| "http client lock failed")?;
+    let mut jar = http_cookie_jar()
+        .lock()
+        .map_err(|_| "cookie jar lock failed")?;
+
+    *jar = Arc::new(reqwest::cookie::Jar::default());
+    clients.retain(|options, _| !options.use_cookie_jar);
+    Ok(())
+}
+
+/// Routes `http_request`, `http_download` and the auth client through `proxy`, or back to the
+/// `HTTPS_PROXY`/`HTTP_PROXY` environment when `None`. Cached clients are dropped so the next
+/// request picks it up.
+#[tauri::command]
+fn http_set_proxy(proxy: Option<String>) -> Result<(), String> {
+    let mut clients = HTTP_CLIENTS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|_| "http client lock failed")?;
+
+    proxy::set_override(proxy)?;
+    clients.clear();
+    Ok(())
+}
+
+/// Measures the round trip of a single HEAD request, for the network-status widget. Redirects
+/// are not followed so only one exchange is timed.
 #[tauri::command]
-async fn http_request(request: HttpRequest) -> Result<HttpResponse, String> {
-    let method = request
-        .method
-        .parse()
-        .map_err(|error| format!("invalid method: {error}"))?;
-    let client = reqwest::Client::new();
-    let mut builder = client.request(method, &request.url);
+async fn net_probe(url: String, timeout_ms: u64) -> Result<ProbeResult, String> {
+    ensure_http_url_allowed(&url).await?;
+    let client = http_client(HttpClientOptions {
+        follow_redirects: false,
+        use_cookie_jar: false,
+        decompress: true,
+    })?;
+
+    let started = Instant::now();
+    let result = client
+        .head(&url)
+        .timeout(Duration::from_millis(timeout_ms))
+        .send()
+        .await;
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    Ok(match result {
+        Ok(response) => ProbeResult {
+            status: Some(response.status().as_u16()),
+            elapsed_ms,
+            failure: None,
+            error: None,
+        },
+        Err(error) => ProbeResult {
+            status: None,
+            elapsed_ms,
+            failure: Some(probe_failure(&error)),
+            error: Some(error.to_string()),
+        },
+    })
+}
+
+fn probe_failure(error: &reqwest::Error) -> ProbeFailure {
+    if error.is_timeout() {
+        return ProbeFailure::Timeout;
+    }
+    if !error.is_connect() {
+        return ProbeFailure::Other;
+    }
+
+    // Resolver failures only show up as a connect error whose cause mentions dns.
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        if cause.to_string().to_ascii_lowercase().contains("dns") {
+            return ProbeFailure::Dns;
+        }
+        source = cause.source();
+    }
+    ProbeFailure::Connect
+}
+
+/// Accepts any case and extension verbs such as `REPORT` or `PROPFIND`, as long as the name is a
+/// valid HTTP token.
+fn parse_http_method(method: &str) -> Result<reqwest::Method, String> {
+    let normalized = method.trim().to_ascii_uppercase();
+    reqwest::Method::from_bytes(normalized.as_bytes())
+        .map_err(|_| format!("invalid method {method:?}: not a valid HTTP method token"))
+}
 
+async fn send_http_request(request: HttpRequest) -> Result<HttpResponse, String> {
+    let method = parse_http_method(&request.method)?;
+    let timeout = request
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(HTTP_DEFAULT_TIMEOUT);
+    if request.body.is_some() && request.json.is_some() {
+        return Err("body and json cannot both be set".to_string());
+    }
+    ensure_http_url_allowed(&request.url).await?;
+    let manual_encoding = request.headers.as_ref().is_some_and(|headers| {
+        headers
+            .keys()
+            .any(|key| key.eq_ignore_ascii_case("accept-encoding"))
+    });
+    let client = http_client(HttpClientOptions {
+        follow_redirects: request.follow_redirects.unwrap_or(true),
+        use_cookie_jar: request.use_cookie_jar.unwrap_or(false),
+        decompress: !manual_encoding,
+    })?;
+    let mut builder = client.request(method, &request.url).timeout(timeout);
+
+    let mut has_content_type = false;
     if let Some(headers) = request.headers {
         for (key, value) in headers {
+            has_content_type |= key.eq_ignore_ascii_case("content-type");
             builder = builder.header(&key, value);
         }
     }
 
+    if let Some(query) = &request.query {
+        builder = builder.query(query);
+    }
+
     if let Some(body) = request.body {
         builder = builder.body(body);
+    } else if let Some(json) = request.json {
+        let body =
+            serde_json::to_vec(&json).map_err(|error| format!("invalid json body: {error}"))?;
+        if !has_content_type {
+            builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json");
+        }
+        builder = builder.body(body);
+    }
+
+    let prepared = builder
+        .build()
+        .map_err(|error| format!("invalid request: {error}"))?;
+    let response = execute_with_retry(&client, prepared, request.retry.as_ref()).await?;
+    let status = response.status().as_u16();
+    let headers = collect_headers(response.headers());
+    let final_url = response.url().to_string();
+    let max_bytes = request.max_bytes.unwrap_or(HTTP_DEFAULT_MAX_BYTES);
+    let bytes = read_limited(response, max_bytes).await?;
+    let (body, encoding) = match request.response_type.unwrap_or_default() {
+        HttpResponseType::Text => (String::from_utf8_lossy(&bytes).into_owned(), "text"),
+        HttpResponseType::Bytes => (STANDARD.encode(bytes), "base64"),
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+        encoding,
+        final_url,
+    })
+}
+
+/// Buffers the body, failing as soon as it exceeds `max_bytes`. `Content-Length` rejects
+/// oversized responses before reading; chunked ones are caught while streaming.
+async fn read_limited(mut response: reqwest::Response, max_bytes: u64) -> Result<Vec<u8>, String> {
+    let too_large = || format!("response too large (limit {max_bytes} bytes)");
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|error| http_error("response read failed", error))?
+    {
+        if (body.len() + chunk.len()) as u64 > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Sends the request, retrying per `retry`. When attempts run out the last response (even a 5xx)
+/// or error is returned as-is.
+async fn execute_with_retry(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    retry: Option<&HttpRetry>,
+) -> Result<reqwest::Response, String> {
+    let retry = retry.filter(|retry| {
+        retry.allow_non_idempotent.unwrap_or(false)
+            || matches!(
+                *request.method(),
+                reqwest::Method::GET
+                    | reqwest::Method::HEAD
+                    | reqwest::Method::PUT
+                    | reqwest::Method::DELETE
+            )
+    });
+    let max_attempts = retry
+        .map(|retry| retry.max_attempts.clamp(1, HTTP_MAX_RETRY_ATTEMPTS))
+        .unwrap_or(1);
+    let base_delay = Duration::from_millis(retry.map(|retry| retry.base_delay_ms).unwrap_or(0));
+
+    let mut attempt = 1;
+    loop {
+        let current = request
+            .try_clone()
+            .ok_or_else(|| "request cannot be retried".to_string())?;
+        let result = client.execute(current).await;
+
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(error) => error.is_connect() || error.is_timeout(),
+        };
+        if !retryable || attempt >= max_attempts {
+            return result.map_err(|error| http_error("request failed", error));
+        }
+
+        tokio::time::sleep(base_delay.saturating_mul(1 << (attempt - 1))).await;
+        attempt += 1;
+    }
+}
+
+/// Streams a response body straight to `destination` instead of buffering it in memory, emitting
+/// `http:progress` events along the way. A partially written file is removed on failure. The body
+/// is written as served: a `.tar.gz` sent with `Content-Encoding: gzip` stays compressed, and a
+/// caller-supplied `Accept-Encoding` is passed through untouched.
+#[tauri::command]
+async fn http_download(
+    app: AppHandle,
+    request: HttpDownloadRequest,
+) -> Result<HttpDownloadResult, String> {
+    let expected_sha256 = request
+        .expected_sha256
+        .as_deref()
+        .map(|digest| digest.trim().to_ascii_lowercase());
+    if let Some(digest) = &expected_sha256 {
+        if digest.len() != 64 || !digest.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return Err(format!(
+                "expectedSha256 is not a hex SHA-256 digest: {digest}"
+            ));
+        }
+    }
+    let mut response = start_download(&request).await?;
+    let status = response.status();
+
+    let mut file = tokio::fs::File::create(&request.destination)
+        .await
+        .map_err(|error| format!("could not create {}: {error}", request.destination))?;
+
+    let emit_progress = |progress| {
+        let _ = app.emit("http:progress", progress);
+    };
+    let result = write_download(&request, &mut response, &mut file, emit_progress)
+        .await
+        .and_then(|(bytes, sha256)| match &expected_sha256 {
+            Some(expected) if *expected != sha256 => Err(format!(
+                "integrity check failed for {}: expected sha256 {expected}, got {sha256}",
+                request.url
+            )),
+            _ => Ok((bytes, sha256)),
+        });
+
+    match result {
+        Ok((bytes, sha256)) => Ok(HttpDownloadResult {
+            status: status.as_u16(),
+            bytes,
+            sha256,
+        }),
+        Err(error) => {
+            drop(file);
+            let _ = tokio::fs::remove_file(&request.destination).await;
+            Err(error)
+        }
+    }
+}
+
+/// Sends the download request and fails on a non-success status. Compressed bodies are not
+/// decoded, so what is written and hashed is exactly what the server published.
+async fn start_download(request: &HttpDownloadRequest) -> Result<reqwest::Response, String> {
+    ensure_http_url_allowed(&request.url).await?;
+    let client = http_client(HttpClientOptions {
+        follow_redirects: true,
+        use_cookie_jar: false,
+        decompress: false,
+    })?;
+    let mut builder = client.get(&request.url);
+
+    if let Some(headers) = &request.headers {
+        for (key, value) in headers {
+            builder = builder.header(key, value);
+        }
     }
 
     let response = builder
         .send()
         .await
-        .map_err(|error| format!("request failed: {error}"))?;
-    let status = response.status().as_u16();
-    let body = response
-        .text()
+        .map_err(|error| http_error("request failed", error))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("download failed with status {}", status.as_u16()));
+    }
+    Ok(response)
+}
+
+/// Writes the body to `file` and returns its length and SHA-256, computed over the bytes as they
+/// land on disk.
+async fn write_download(
+    request: &HttpDownloadRequest,
+    response: &mut reqwest::Response,
+    file: &mut tokio::fs::File,
+    mut emit_progress: impl FnMut(HttpProgress),
+) -> Result<(u64, String), String> {
+    let mut progress = HttpProgress {
+        url: request.url.clone(),
+        destination: request.destination.clone(),
+        downloaded: 0,
+        total: response.content_length(),
+    };
+    let mut last_emit = Instant::now();
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|error| http_error("download failed", error))?
+    {
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(|error| format!("write failed: {error}"))?;
+        progress.downloaded += chunk.len() as u64;
+
+        if last_emit.elapsed() >= HTTP_PROGRESS_INTERVAL {
+            emit_progress(progress.clone());
+            last_emit = Instant::now();
+        }
+    }
+
+    file.flush()
+        .await
+        .map_err(|error| format!("write failed: {error}"))?;
+    emit_progress(progress.clone());
+
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok((progress.downloaded, sha256))
+}
+
+/// Checks the url's host against `http_allowed_hosts` and, for `localhost` and literal addresses,
+/// the private range rule. Returns whether the host is listed exactly, which permits private
+/// addresses.
+fn check_http_host(url: &url::Url) -> Result<bool, String> {
+    let host = url.host().ok_or_else(|| format!("url has no host: {url}"))?;
+    let name = match &host {
+        url::Host::Domain(domain) => domain.to_ascii_lowercase(),
+        url::Host::Ipv4(ip) => ip.to_string(),
+        url::Host::Ipv6(ip) => ip.to_string(),
+    };
+
+    let allowed = http_allowed_hosts();
+    if allowed.contains(&name) {
+        return Ok(true);
+    }
+
+    let matches_pattern = allowed.is_empty()
+        || allowed.iter().any(|entry| {
+            entry == "*"
+                || entry
+                    .strip_prefix("*.")
+                    .is_some_and(|suffix| name.ends_with(&format!(".{suffix}")))
+        });
+    if !matches_pattern {
+        return Err(format!("host not allowed: {name}"));
+    }
+
+    let private = match host {
+        url::Host::Domain(_) => name == "localhost" || name.ends_with(".localhost"),
+        url::Host::Ipv4(ip) => is_private_ip(IpAddr::V4(ip)),
+        url::Host::Ipv6(ip) => is_private_ip(IpAddr::V6(ip)),
+    };
+    if private {
+        return Err(format!("private address not allowed: {name}"));
+    }
+
+    Ok(false)
+}
+
+/// Rejects urls that `check_http_host` refuses, and public names that resolve into private
+/// ranges. reqwest resolves the name again when connecting, so the DNS part is best-effort.
+pub(crate) async fn ensure_http_url_allowed(url: &str) -> Result<(), String> {
+    let url = url::Url::parse(url).map_err(|error| format!("invalid url: {error}"))?;
+    if check_http_host(&url)? {
+        return Ok(());
+    }
+
+    let Some(url::Host::Domain(domain)) = url.host() else {
+        return Ok(());
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs = tokio::net::lookup_host((domain, port))
         .await
-        .map_err(|error| format!("response read failed: {error}"))?;
+        .map_err(|error| format!("could not resolve {domain}: {error}"))?;
+
+    for addr in addrs {
+        if is_private_ip(addr.ip()) {
+            return Err(format!("private address not allowed: {domain}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // 100.64.0.0/10, carrier-grade NAT
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(embedded) = ip.to_ipv4_mapped().or_else(|| embedded_ipv4(ip)) {
+                return is_private_ip(IpAddr::V4(embedded));
+            }
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // fc00::/7 unique local and fe80::/10 link-local
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// The IPv4 address a NAT64 (`64:ff9b::/96`) or 6to4 (`2002::/16`) address is relayed to, so a
+/// private address can't be reached through the gateway.
+fn embedded_ipv4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let octets = ip.octets();
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]));
+    }
+    if segments[0] == 0x2002 {
+        return Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]));
+    }
+    None
+}
+
+/// Timeouts get a fixed message so the UI can offer a retry without parsing reqwest's wording.
+fn http_error(context: &str, error: reqwest::Error) -> String {
+    if error.is_timeout() {
+        "request timed out".to_string()
+    } else {
+        format!("{context}: {error}")
+    }
+}
 
-    Ok(HttpResponse { status, body })
+/// Flattens response headers the way browsers expose them: lowercase names, repeated headers
+/// joined with ", ".
+fn collect_headers(headers: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    let mut collected: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        collected
+            .entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+    collected
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
+        // First, so warnings from the other plugins and from `setup` are recorded.
+        .plugin(logging::plugin())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_store::Builder::default().build())
-        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
-            deeplink::handle_single_instance_args(app, _args);
-            if let Some(window) = app.get_webview_window("main") {
-                let _ = window.show();
-                let _ = window.set_focus();
-            }
+        .plugin(tauri_plugin_single_instance::init(|app, args, _cwd| {
+            // Restore the window first; a route from `args` is applied once it is visible.
+            deeplink::focus_main_window(app);
+            deeplink::handle_single_instance_args(app, args);
         }))
         .invoke_handler(tauri::generate_handler![
             get_app_info,
+            open_external,
             http_request,
+            http_download,
+            http_request_batch,
+            websocket::ws_connect,
+            websocket::ws_send,
+            websocket::ws_close,
+            http_abort,
+            http_clear_cookies,
+            http_set_proxy,
+            net_probe,
             discord_update_presence,
+            discord_update_timestamps,
+            discord_connect,
+            discord_enable_autoconnect,
             discord_clear_presence,
+            discord_disconnect,
+            discord_set_idle_timeout,
+            discord_set_reconnect_backoff,
+            discord_is_connected,
+            discord_get_user,
+            auth::oauth_set_provider,
             auth::oauth_prepare_login,
+            auth::oauth_prepare_login_url,
+            auth::oauth_prepare_loopback_login,
+            auth::oauth_await_loopback_callback,
+            auth::oauth_prepare_device_login,
+            auth::oauth_poll_device_token,
+            auth::oauth_import_refresh_token,
             auth::oauth_handle_callback,
+            auth::oauth_cancel_login,
             auth::oauth_refresh_if_needed,
             auth::oauth_logout,
             auth::oauth_get_auth_state,
+            auth::oauth_seconds_until_expiry,
+            auth::oauth_list_accounts,
             auth::oauth_get_access_token,
-            deeplink::deeplink_get_current_route
+            auth::oauth_get_fresh_access_token,
+            auth::oauth_get_user_info,
+            auth::oauth_introspect_token,
+            auth::set_token_storage_preference,
+            auth::auth_export_debug_state,
+            diagnostics::diagnostics_check_keyring,
+            diagnostics::diagnostics_export_bundle,
+            logging::logs_get_path,
+            settings::settings_get,
+            settings::settings_set,
+            deeplink::deeplink_register_routes,
+            deeplink::deeplink_get_current_route,
+            deeplink::deeplink_clear_route,
+            deeplink::deeplink_check_registration,
+            deeplink::deeplink_register,
+            deeplink::deeplink_simulate,
+            deeplink::deeplink_consume_pending_route,
+            window_state::window_save_state,
+            window_state::window_restore_state,
+            window_state::window_list_monitors,
+            window_state::window_move_to_monitor,
+            #[cfg(desktop)]
+            shortcut::shortcut_set_toggle
         ])
         .setup(|app| {
             app.manage(auth::AuthState::new());
+            let _ = DISCORD_APP.set(app.handle().clone());
             app.manage(deeplink::DeepLinkState::new());
+            if let Err(err) = auth::migrate_legacy_storage(app.handle()) {
+                log::warn!("Could not move stored tokens into the provider namespace: {err}");
+            }
+            auth::spawn_expiry_watcher(app.handle().clone());
             deeplink::setup_deeplinks(app.handle());
+            window_state::setup_window_state(app.handle());
+            #[cfg(all(desktop, feature = "tray"))]
+            if let Err(err) = tray::setup_tray(app.handle()) {
+                log::warn!("Failed to create tray icon: {err}");
+            }
             #[cfg(desktop)]
             app.handle()
                 .plugin(tauri_plugin_updater::Builder::new().build())?;
-            if cfg!(debug_assertions) {
-                app.handle().plugin(
-                    tauri_plugin_log::Builder::default()
-                        .level(log::LevelFilter::Info)
-                        .build(),
-                )?;
+            #[cfg(desktop)]
+            {
+                app.manage(shortcut::ToggleShortcutState::new());
+                app.handle().plugin(shortcut::plugin())?;
+                shortcut::setup_shortcuts(app.handle());
             }
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                discord_shutdown();
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Once;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    /// `hello from vision`, gzip-compressed.
+    const GZIP_BODY: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0x48, 0x2b, 0xca, 0xcf, 0x55, 0x28, 0xcb, 0x2c, 0xce, 0xcc, 0xcf, 0x03, 0x00, 0x7b,
+        0x6b, 0xbd, 0xfd, 0x11, 0x00, 0x00, 0x00,
+    ];
+
+    /// Lets the requests below reach the mock server, which loopback rules would otherwise block.
+    fn allow_loopback() {
+        static ALLOW: Once = Once::new();
+        ALLOW.call_once(|| std::env::set_var("VISION_HTTP_ALLOWED_HOSTS", "127.0.0.1"));
+    }
+
+    /// Answers one request with `GZIP_BODY` sent as `Content-Encoding: gzip`, whatever the
+    /// request asked for. Returns the url to request.
+    async fn serve_gzip_once() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                GZIP_BODY.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(GZIP_BODY).await.unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn download_digest_covers_the_bytes_as_served() {
+        allow_loopback();
+        let destination =
+            std::env::temp_dir().join(format!("vision-download-test-{}", std::process::id()));
+        let (bytes, sha256) = tauri::async_runtime::block_on(async {
+            let request: HttpDownloadRequest = serde_json::from_value(serde_json::json!({
+                "url": serve_gzip_once().await,
+                "destination": destination.to_string_lossy(),
+            }))
+            .unwrap();
+            let mut response = start_download(&request).await.unwrap();
+            let mut file = tokio::fs::File::create(&destination).await.unwrap();
+            write_download(&request, &mut response, &mut file, |_| {}).await
+        })
+        .unwrap();
+
+        let written = std::fs::read(&destination).unwrap();
+        let _ = std::fs::remove_file(&destination);
+        assert_eq!(written, GZIP_BODY);
+        assert_eq!(bytes, GZIP_BODY.len() as u64);
+        assert_eq!(
+            sha256,
+            "252f705ee42dadd877a6baa0942898f411563fae69e970ee0b6c0bd592f08c54"
+        );
+    }
+
+    fn get_request(url: &str, extra: serde_json::Value) -> HttpRequest {
+        let mut request = serde_json::json!({ "method": "GET", "url": url });
+        if let (Some(request), Some(extra)) = (request.as_object_mut(), extra.as_object()) {
+            request.extend(extra.clone());
+        }
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[test]
+    fn request_id_in_flight_is_rejected() {
+        tauri::async_runtime::block_on(async {
+            let earlier = tokio::spawn(std::future::pending::<()>());
+            http_in_flight()
+                .lock()
+                .unwrap()
+                .insert("duplicate".to_string(), earlier.abort_handle());
+
+            let request = get_request("http://127.0.0.1:9/", serde_json::json!({}));
+            let Err(error) = send_tracked_request("duplicate".to_string(), request).await else {
+                panic!("a second request with the same id was accepted");
+            };
+            assert!(error.contains("already in flight"), "{error}");
+
+            // The earlier request is still the one `http_abort` cancels.
+            assert!(http_abort("duplicate".to_string()));
+            assert!(earlier.await.unwrap_err().is_cancelled());
+        });
+    }
+
+    #[test]
+    fn gzip_response_is_decoded() {
+        allow_loopback();
+        let response = tauri::async_runtime::block_on(async {
+            let url = serve_gzip_once().await;
+            send_http_request(get_request(&url, serde_json::json!({}))).await
+        })
+        .unwrap();
+        assert_eq!(response.body, "hello from vision");
+        assert_eq!(response.encoding, "text");
+    }
+
+    #[test]
+    fn manual_accept_encoding_returns_raw_bytes() {
+        allow_loopback();
+        let response = tauri::async_runtime::block_on(async {
+            let url = serve_gzip_once().await;
+            let extra = serde_json::json!({
+                "headers": { "Accept-Encoding": "gzip" },
+                "responseType": "bytes",
+            });
+            send_http_request(get_request(&url, extra)).await
+        })
+        .unwrap();
+        assert_eq!(response.body, STANDARD.encode(GZIP_BODY));
+        assert_eq!(response.encoding, "base64");
+    }
+
+    #[test]
+    fn lowercase_method_is_uppercased() {
+        assert_eq!(parse_http_method("patch").unwrap(), reqwest::Method::PATCH);
+    }
+
+    #[test]
+    fn custom_verb_becomes_an_extension_method() {
+        let method = parse_http_method("report").unwrap();
+        assert_eq!(method.as_str(), "REPORT");
+    }
+
+    #[test]
+    fn invalid_token_names_the_method() {
+        let error = parse_http_method("GE T").unwrap_err();
+        assert!(error.contains("\"GE T\""), "{error}");
+    }
+
+    #[test]
+    fn throttle_waits_out_the_rest_of_the_interval() {
+        let now = Instant::now();
+        let mut throttle = DiscordThrottle::default();
+        assert_eq!(throttle.wait(now), None);
+
+        throttle.last_update = Some(now);
+        assert_eq!(throttle.wait(now), Some(DISCORD_UPDATE_INTERVAL));
+        let later = now + Duration::from_secs(5);
+        assert_eq!(
+            throttle.wait(later),
+            Some(DISCORD_UPDATE_INTERVAL - Duration::from_secs(5))
+        );
+        assert_eq!(throttle.wait(now + DISCORD_UPDATE_INTERVAL), None);
+    }
+
+    #[test]
+    fn dropping_the_parked_update_retires_its_flush() {
+        let mut throttle = DiscordThrottle::default();
+        let scheduled = throttle.generation;
+        throttle.drop_pending();
+        assert!(throttle.pending.is_none());
+        assert_ne!(throttle.generation, scheduled);
+    }
+
+    fn private(ip: &str) -> bool {
+        is_private_ip(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn multicast_is_private() {
+        assert!(private("224.0.0.1"));
+        assert!(private("239.255.255.250"));
+        assert!(private("ff02::1"));
+        assert!(private("ff0e::1"));
+    }
+
+    #[test]
+    fn nat64_follows_the_embedded_address() {
+        assert!(private("64:ff9b::10.0.0.1"));
+        assert!(private("64:ff9b::127.0.0.1"));
+        assert!(!private("64:ff9b::8.8.8.8"));
+    }
+
+    #[test]
+    fn six_to_four_follows_the_embedded_address() {
+        // 2002:c0a8:0101:: relays to 192.168.1.1, 2002:0808:0808:: to 8.8.8.8.
+        assert!(private("2002:c0a8:101::1"));
+        assert!(private("2002:7f00:1::"));
+        assert!(!private("2002:808:808::1"));
+    }
+
+    #[test]
+    fn public_addresses_are_allowed() {
+        assert!(!private("93.184.216.34"));
+        assert!(!private("2606:2800:220:1:248:1893:25c8:1946"));
+    }
 }
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_log::{RotationStrategy, Target, TargetKind};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const LOG_FILE_NAME: &str = "vision-desktop";
+/// Size at which the log file is rotated on the next launch.
+const LOG_MAX_FILE_SIZE: u128 = 5 * 1024 * 1024;
+/// Rotated files kept next to the current one, including it.
+const LOG_RETAINED_FILES: usize = 5;
+/// Target of the Discord presence payload lines, which are written at debug level so users can
+/// compare the keys they send with their uploaded asset names.
+pub const DISCORD_PRESENCE_TARGET: &str = "discord_presence";
+
+/// Writes one JSON object per line (`timestamp`, `level`, `target`, `message`) to a rotated file
+/// in the app log directory, in release builds too so crash reports come with logs. Debug builds
+/// also log to stdout.
+pub fn plugin<R: Runtime>() -> tauri::plugin::TauriPlugin<R> {
+    let mut targets = vec![Target::new(TargetKind::LogDir {
+        file_name: Some(LOG_FILE_NAME.into()),
+    })];
+    if cfg!(debug_assertions) {
+        targets.push(Target::new(TargetKind::Stdout));
+    }
+
+    tauri_plugin_log::Builder::new()
+        .clear_targets()
+        .targets(targets)
+        .level(log::LevelFilter::Info)
+        .level_for(DISCORD_PRESENCE_TARGET, log::LevelFilter::Debug)
+        .max_file_size(LOG_MAX_FILE_SIZE)
+        .rotation_strategy(RotationStrategy::KeepSome(LOG_RETAINED_FILES))
+        .format(|out, message, record| {
+            let timestamp = OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default();
+            let line = serde_json::json!({
+                "timestamp": timestamp,
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": message.to_string(),
+            });
+            out.finish(format_args!("{line}"))
+        })
+        .build()
+}
+
+/// Path of the current log file, for "reveal logs" in bug reports. The file may not exist yet if
+/// nothing has been logged.
+#[tauri::command]
+pub fn logs_get_path(app: AppHandle) -> Result<String, String> {
+    log_file_path(&app).map(|path| path.to_string_lossy().into_owned())
+}
+
+pub fn log_file_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_log_dir().map_err(|err| err.to_string())?;
+    Ok(dir.join(format!("{LOG_FILE_NAME}.log")))
+}
@@ -0,0 +1,132 @@
+use std::sync::{Mutex, OnceLock};
+
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use url::Url;
+
+/// Proxy set through `http_set_proxy`; takes precedence over the environment.
+static PROXY_OVERRIDE: OnceLock<Mutex<Option<String>>> = OnceLock::new();
+
+fn proxy_override() -> &'static Mutex<Option<String>> {
+    PROXY_OVERRIDE.get_or_init(|| Mutex::new(None))
+}
+
+/// Sets or clears the proxy used for every outbound request. Callers must drop cached clients
+/// afterwards, since a client keeps the proxy it was built with.
+pub fn set_override(proxy: Option<String>) -> Result<(), String> {
+    let proxy = proxy
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty());
+    if let Some(value) = &proxy {
+        Proxy::all(value.as_str()).map_err(|err| format!("invalid proxy url: {err}"))?;
+    }
+
+    *proxy_override().lock().map_err(|_| "proxy lock failed")? = proxy;
+    Ok(())
+}
+
+/// Routes the client through the override proxy, or else `HTTPS_PROXY`/`HTTP_PROXY`. Hosts in
+/// `NO_PROXY` always connect directly. Without any proxy configured the builder is unchanged.
+pub fn apply(mut builder: ClientBuilder) -> Result<ClientBuilder, String> {
+    let no_proxy = NoProxy::from_env();
+
+    let override_url = proxy_override()
+        .lock()
+        .map_err(|_| "proxy lock failed")?
+        .clone();
+    if let Some(url) = override_url {
+        let proxy = Proxy::all(url.as_str()).map_err(|err| format!("invalid proxy url: {err}"))?;
+        return Ok(builder.proxy(proxy.no_proxy(no_proxy)));
+    }
+
+    if let Some(url) = env_proxy("HTTPS_PROXY") {
+        let proxy =
+            Proxy::https(url.as_str()).map_err(|err| format!("invalid HTTPS_PROXY: {err}"))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy.clone()));
+    }
+    if let Some(url) = env_proxy("HTTP_PROXY") {
+        let proxy =
+            Proxy::http(url.as_str()).map_err(|err| format!("invalid HTTP_PROXY: {err}"))?;
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+    Ok(builder)
+}
+
+/// The proxy a WebSocket to `url` has to tunnel through, picked like `apply` picks one for HTTP:
+/// the override, or else `HTTPS_PROXY` for `wss` and `HTTP_PROXY` for `ws`. `None` when no proxy
+/// is configured or `NO_PROXY` lists the host.
+pub fn for_websocket(url: &Url) -> Result<Option<Url>, String> {
+    let host = url.host_str().unwrap_or_default();
+    if env_proxy("NO_PROXY").is_some_and(|no_proxy| bypasses(host, &no_proxy)) {
+        return Ok(None);
+    }
+
+    let override_url = proxy_override()
+        .lock()
+        .map_err(|_| "proxy lock failed")?
+        .clone();
+    let (proxy, name) = match override_url {
+        Some(url) => (url, "proxy url"),
+        None => {
+            let name = if url.scheme() == "wss" {
+                "HTTPS_PROXY"
+            } else {
+                "HTTP_PROXY"
+            };
+            let Some(url) = env_proxy(name) else {
+                return Ok(None);
+            };
+            (url, name)
+        }
+    };
+
+    // Like reqwest, a bare `host:port` means an http:// proxy.
+    let proxy = if proxy.contains("://") {
+        proxy
+    } else {
+        format!("http://{proxy}")
+    };
+    Url::parse(&proxy)
+        .map(Some)
+        .map_err(|err| format!("invalid {name}: {err}"))
+}
+
+/// Whether a `NO_PROXY` list exempts `host`: `*` matches every host, `example.com` and
+/// `.example.com` match the domain and its subdomains. IP ranges are not supported.
+fn bypasses(host: &str, no_proxy: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{domain}"))
+        })
+}
+
+fn env_proxy(name: &str) -> Option<String> {
+    std::env::var(name)
+        .or_else(|_| std::env::var(name.to_lowercase()))
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_proxy_matches_domains_and_subdomains() {
+        assert!(bypasses("example.com", "example.com"));
+        assert!(bypasses("chat.example.com", "other.org, .example.com"));
+        assert!(bypasses("Chat.Example.com", "example.com"));
+        assert!(bypasses("anything.net", "*"));
+    }
+
+    #[test]
+    fn no_proxy_does_not_match_lookalike_hosts() {
+        assert!(!bypasses("notexample.com", "example.com"));
+        assert!(!bypasses("example.com.evil.net", "example.com"));
+        assert!(!bypasses("example.com", ""));
+    }
+}
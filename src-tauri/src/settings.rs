@@ -0,0 +1,39 @@
+use tauri::AppHandle;
+use tauri_plugin_store::StoreExt;
+
+// Shared with the toggle shortcut, whose key is deliberately not listed below: it must go through
+// `shortcut_set_toggle` so the new accelerator is registered.
+const STORE_PATH: &str = "settings.json";
+
+/// Keys the frontend may read and write, matching `AppSettings` on the settings page. Anything
+/// else, and every other store file (notably `auth.json`), is out of reach.
+const ALLOWED_KEYS: &[&str] = &[
+    "discordPresenceEnabled",
+    "autoRefreshEnabled",
+    "projectCacheEnabled",
+    "toastEnabled",
+];
+
+/// Returns the saved value, or `None` when the setting was never written.
+#[tauri::command]
+pub fn settings_get(app: AppHandle, key: String) -> Result<Option<serde_json::Value>, String> {
+    ensure_allowed(&key)?;
+    let store = app.store(STORE_PATH).map_err(|err| err.to_string())?;
+    Ok(store.get(&key))
+}
+
+#[tauri::command]
+pub fn settings_set(app: AppHandle, key: String, value: serde_json::Value) -> Result<(), String> {
+    ensure_allowed(&key)?;
+    let store = app.store(STORE_PATH).map_err(|err| err.to_string())?;
+    store.set(key, value);
+    store.save().map_err(|err| err.to_string())
+}
+
+fn ensure_allowed(key: &str) -> Result<(), String> {
+    if ALLOWED_KEYS.contains(&key) {
+        Ok(())
+    } else {
+        Err(format!("unknown setting: {key}"))
+    }
+}
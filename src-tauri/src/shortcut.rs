@@ -0,0 +1,101 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tauri::{AppHandle, Manager, Runtime};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
+use tauri_plugin_store::StoreExt;
+
+const STORE_PATH: &str = "settings.json";
+const STORE_KEY: &str = "toggle_shortcut";
+const DEFAULT_TOGGLE_SHORTCUT: &str = "CmdOrCtrl+Shift+V";
+
+/// The currently registered toggle shortcut, so rebinding can release it.
+pub struct ToggleShortcutState {
+    toggle: Mutex<Option<Shortcut>>,
+}
+
+impl ToggleShortcutState {
+    pub fn new() -> Self {
+        Self {
+            toggle: Mutex::new(None),
+        }
+    }
+}
+
+pub fn plugin<R: Runtime>() -> tauri::plugin::TauriPlugin<R> {
+    tauri_plugin_global_shortcut::Builder::new()
+        .with_handler(handle_shortcut)
+        .build()
+}
+
+/// Registers the saved toggle shortcut, or the default one on first launch.
+pub fn setup_shortcuts(app: &AppHandle) {
+    let accelerator = load_accelerator(app).unwrap_or_else(|| DEFAULT_TOGGLE_SHORTCUT.into());
+    if let Err(err) = register_toggle(app, &accelerator) {
+        log::warn!("Failed to register toggle shortcut {accelerator}: {err}");
+    }
+}
+
+/// Rebinds the show/hide shortcut and saves it. The previous shortcut stays active when the
+/// new one cannot be registered, e.g. because another application already owns it.
+#[tauri::command]
+pub fn shortcut_set_toggle(app: AppHandle, accelerator: String) -> Result<(), String> {
+    register_toggle(&app, &accelerator)?;
+
+    let store = app.store(STORE_PATH).map_err(|err| err.to_string())?;
+    store.set(STORE_KEY, accelerator);
+    store.save().map_err(|err| err.to_string())
+}
+
+fn register_toggle(app: &AppHandle, accelerator: &str) -> Result<(), String> {
+    let shortcut = Shortcut::from_str(accelerator)
+        .map_err(|err| format!("invalid shortcut {accelerator}: {err}"))?;
+
+    let state = app.state::<ToggleShortcutState>();
+    let mut current = state.toggle.lock().map_err(|_| "lock failed")?;
+    if *current == Some(shortcut) {
+        return Ok(());
+    }
+
+    let shortcuts = app.global_shortcut();
+    if let Some(previous) = *current {
+        let _ = shortcuts.unregister(previous);
+    }
+
+    if let Err(err) = shortcuts.register(shortcut) {
+        if let Some(previous) = *current {
+            let _ = shortcuts.register(previous);
+        }
+        return Err(format!(
+            "shortcut {accelerator} could not be registered; it may already be in use: {err}"
+        ));
+    }
+
+    *current = Some(shortcut);
+    Ok(())
+}
+
+fn load_accelerator(app: &AppHandle) -> Option<String> {
+    let store = app.store(STORE_PATH).ok()?;
+    store.get(STORE_KEY)?.as_str().map(str::to_string)
+}
+
+fn handle_shortcut<R: Runtime>(app: &AppHandle<R>, _shortcut: &Shortcut, event: ShortcutEvent) {
+    if event.state() != ShortcutState::Pressed {
+        return;
+    }
+
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+
+    let visible = window.is_visible().unwrap_or(false);
+    let focused = window.is_focused().unwrap_or(false);
+    if visible && focused {
+        let _ = window.hide();
+    } else {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
@@ -0,0 +1,58 @@
+use tauri::menu::{Menu, MenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Emitter};
+
+use crate::deeplink;
+
+const TRAY_ID: &str = "main";
+const MENU_OPEN: &str = "open";
+const MENU_TOGGLE_PRESENCE: &str = "toggle_presence";
+const MENU_QUIT: &str = "quit";
+
+/// Adds the tray icon. Every menu action is also emitted as `tray:action` with the item id, so
+/// the frontend can react; "Toggle Presence" is handled there because it owns that setting.
+pub fn setup_tray(app: &AppHandle) -> tauri::Result<()> {
+    let open = MenuItem::with_id(app, MENU_OPEN, "Open", true, None::<&str>)?;
+    let toggle_presence = MenuItem::with_id(
+        app,
+        MENU_TOGGLE_PRESENCE,
+        "Toggle Presence",
+        true,
+        None::<&str>,
+    )?;
+    let quit = MenuItem::with_id(app, MENU_QUIT, "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&open, &toggle_presence, &quit])?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("Vision Desktop")
+        .menu(&menu)
+        .show_menu_on_left_click(false)
+        .on_menu_event(|app, event| handle_menu_event(app, event.id().as_ref()))
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                deeplink::focus_main_window(tray.app_handle());
+            }
+        });
+
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+
+    builder.build(app)?;
+    Ok(())
+}
+
+fn handle_menu_event(app: &AppHandle, id: &str) {
+    let _ = app.emit("tray:action", id);
+
+    match id {
+        MENU_OPEN => deeplink::focus_main_window(app),
+        MENU_QUIT => app.exit(0),
+        _ => {}
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+/// Longest reply to a proxy `CONNECT` that is read before giving up.
+const MAX_PROXY_REPLY_BYTES: usize = 8 * 1024;
+
+/// Outgoing queues of the open connections, keyed by connection id. An entry is removed when the
+/// connection closes, from either side.
+static WS_CONNECTIONS: OnceLock<Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>> =
+    OnceLock::new();
+static WS_NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn connections() -> &'static Mutex<HashMap<String, mpsc::UnboundedSender<Message>>> {
+    WS_CONNECTIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Payload of `ws:message`. Binary frames are base64 encoded and flagged with `binary`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WsMessage {
+    connection_id: String,
+    data: String,
+    binary: bool,
+}
+
+/// Payload of `ws:closed`; `code` and `reason` come from the server's close frame, if any.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WsClosed {
+    connection_id: String,
+    code: Option<u16>,
+    reason: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WsError {
+    connection_id: String,
+    error: String,
+}
+
+/// Opens a WebSocket and returns its connection id. Incoming frames arrive as `ws:message`, and
+/// `ws:closed` follows once the connection ends for any reason, after a `ws:error` if it failed.
+/// The url passes the same host allowlist as `http_request`, and the connection goes through the
+/// configured proxy (see `http_set_proxy`) as an HTTP `CONNECT` tunnel.
+#[tauri::command]
+pub async fn ws_connect(
+    app: AppHandle,
+    url: String,
+    headers: Option<HashMap<String, String>>,
+) -> Result<String, String> {
+    let parsed = Url::parse(&url).map_err(|err| format!("invalid url: {err}"))?;
+    if !matches!(parsed.scheme(), "ws" | "wss") {
+        return Err(format!("websocket url must be ws or wss: {url}"));
+    }
+    crate::ensure_http_url_allowed(&url).await?;
+
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|err| format!("invalid request: {err}"))?;
+    for (name, value) in headers.unwrap_or_default() {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|err| format!("invalid header name {name:?}: {err}"))?;
+        let value = HeaderValue::from_str(&value)
+            .map_err(|err| format!("invalid header value for {name}: {err}"))?;
+        request.headers_mut().insert(name, value);
+    }
+
+    let proxy = crate::proxy::for_websocket(&parsed)?;
+    let stream = connect(request, &parsed, proxy).await?;
+    let (mut sink, mut source) = stream.split();
+
+    let connection_id = format!("ws-{}", WS_NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<Message>();
+    connections()
+        .lock()
+        .map_err(|_| "websocket lock failed")?
+        .insert(connection_id.clone(), sender);
+
+    tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            let closing = message.is_close();
+            if sink.send(message).await.is_err() || closing {
+                break;
+            }
+        }
+    });
+
+    let id = connection_id.clone();
+    tokio::spawn(async move {
+        let mut close = None;
+        while let Some(frame) = source.next().await {
+            let message = match frame {
+                Ok(Message::Text(text)) => WsMessage {
+                    connection_id: id.clone(),
+                    data: text.to_string(),
+                    binary: false,
+                },
+                Ok(Message::Binary(bytes)) => WsMessage {
+                    connection_id: id.clone(),
+                    data: STANDARD.encode(bytes),
+                    binary: true,
+                },
+                Ok(Message::Close(frame)) => {
+                    close = frame;
+                    break;
+                }
+                // Pings are answered by tungstenite itself.
+                Ok(_) => continue,
+                Err(err) => {
+                    let _ = app.emit(
+                        "ws:error",
+                        WsError {
+                            connection_id: id.clone(),
+                            error: err.to_string(),
+                        },
+                    );
+                    break;
+                }
+            };
+            let _ = app.emit("ws:message", message);
+        }
+
+        if let Ok(mut connections) = connections().lock() {
+            connections.remove(&id);
+        }
+        let _ = app.emit(
+            "ws:closed",
+            WsClosed {
+                connection_id: id,
+                code: close.as_ref().map(|frame| frame.code.into()),
+                reason: close.map(|frame| frame.reason.to_string()),
+            },
+        );
+    });
+
+    Ok(connection_id)
+}
+
+/// Connects to `url` directly, or through an HTTP `CONNECT` tunnel when `proxy` is set, and runs
+/// the TLS and WebSocket handshakes.
+async fn connect(
+    request: Request,
+    url: &Url,
+    proxy: Option<Url>,
+) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, String> {
+    let connected = match proxy {
+        Some(proxy) => {
+            let tunnel = open_tunnel(&proxy, url).await?;
+            tokio_tungstenite::client_async_tls(request, tunnel).await
+        }
+        None => tokio_tungstenite::connect_async(request).await,
+    };
+    let (stream, _) = connected.map_err(|err| format!("websocket connect failed: {err}"))?;
+    Ok(stream)
+}
+
+/// Asks `proxy` for a tunnel to the host and port of `target`, authenticating with the proxy
+/// url's credentials if it has any.
+async fn open_tunnel(proxy: &Url, target: &Url) -> Result<TcpStream, String> {
+    if proxy.scheme() != "http" {
+        return Err(format!(
+            "websockets can only tunnel through http:// proxies, not {}://",
+            proxy.scheme()
+        ));
+    }
+    let proxy_host = proxy.host_str().ok_or("proxy url has no host")?;
+    let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+    let target_host = target.host_str().ok_or("websocket url has no host")?;
+    let target_port = target
+        .port_or_known_default()
+        .ok_or("websocket url has no port")?;
+    let authority = format!("{target_host}:{target_port}");
+
+    let mut stream = TcpStream::connect((proxy_host, proxy_port))
+        .await
+        .map_err(|err| format!("proxy connect failed: {err}"))?;
+
+    let mut head = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if !proxy.username().is_empty() {
+        let decode = |value: &str| {
+            percent_encoding::percent_decode_str(value)
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        let credentials = format!(
+            "{}:{}",
+            decode(proxy.username()),
+            decode(proxy.password().unwrap_or_default())
+        );
+        head.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            STANDARD.encode(credentials)
+        ));
+    }
+    head.push_str("\r\n");
+    stream
+        .write_all(head.as_bytes())
+        .await
+        .map_err(|err| format!("proxy connect failed: {err}"))?;
+
+    // Read byte by byte, so nothing the server sends through the tunnel is swallowed.
+    let mut reply = Vec::new();
+    let mut byte = [0u8; 1];
+    while !reply.ends_with(b"\r\n\r\n") {
+        if reply.len() >= MAX_PROXY_REPLY_BYTES {
+            return Err("proxy reply too long".to_string());
+        }
+        let read = stream
+            .read(&mut byte)
+            .await
+            .map_err(|err| format!("proxy connect failed: {err}"))?;
+        if read == 0 {
+            return Err("proxy closed the connection".to_string());
+        }
+        reply.push(byte[0]);
+    }
+
+    let reply = String::from_utf8_lossy(&reply);
+    let status_line = reply.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("proxy refused the tunnel: {status_line}"));
+    }
+    Ok(stream)
+}
+
+/// Queues a text frame on the connection.
+#[tauri::command]
+pub fn ws_send(connection_id: String, message: String) -> Result<(), String> {
+    let connections = connections().lock().map_err(|_| "websocket lock failed")?;
+    let sender = connections
+        .get(&connection_id)
+        .ok_or_else(|| format!("no open websocket {connection_id}"))?;
+    sender
+        .send(Message::text(message))
+        .map_err(|_| format!("websocket {connection_id} is closed"))
+}
+
+/// Starts the close handshake. `ws:closed` is emitted once the server acknowledges it. Returns
+/// whether the connection was still open.
+#[tauri::command]
+pub fn ws_close(connection_id: String) -> bool {
+    let sender = connections()
+        .lock()
+        .ok()
+        .and_then(|mut connections| connections.remove(&connection_id));
+    sender.is_some_and(|sender| sender.send(Message::Close(None)).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connects_through_an_http_connect_proxy() {
+        let (message, head) = tauri::async_runtime::block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = Url::parse(&format!(
+                "http://user:p%40ss@{}",
+                listener.local_addr().unwrap()
+            ))
+            .unwrap();
+            let server = tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).await.unwrap();
+                    head.push(byte[0]);
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+                socket
+                    .send(Message::text("through the tunnel"))
+                    .await
+                    .unwrap();
+                String::from_utf8(head).unwrap()
+            });
+
+            let url = Url::parse("ws://chat.example.com/socket").unwrap();
+            let request = url.as_str().into_client_request().unwrap();
+            let mut socket = connect(request, &url, Some(proxy)).await.unwrap();
+            let message = socket.next().await.unwrap().unwrap();
+            (message, server.await.unwrap())
+        });
+
+        assert_eq!(message.to_text().unwrap(), "through the tunnel");
+        assert!(
+            head.starts_with("CONNECT chat.example.com:80 HTTP/1.1\r\n"),
+            "{head}"
+        );
+        let credentials = STANDARD.encode("user:p@ss");
+        assert!(head.contains(&format!("Proxy-Authorization: Basic {credentials}\r\n")));
+    }
+
+    #[test]
+    fn refused_tunnel_is_an_error() {
+        let error = tauri::async_runtime::block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let _ = stream
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                    .await;
+            });
+            let target = Url::parse("wss://chat.example.com/socket").unwrap();
+            open_tunnel(&proxy, &target).await.unwrap_err()
+        });
+        assert!(error.contains("407"), "{error}");
+    }
+}
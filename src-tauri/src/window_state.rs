@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+use tauri::{
+    AppHandle, Manager, Monitor, PhysicalPosition, PhysicalSize, WebviewWindow, WindowEvent,
+};
+use tauri_plugin_store::StoreExt;
+
+const STORE_PATH: &str = "window.json";
+const STORE_KEY: &str = "main_window";
+const MAIN_WINDOW: &str = "main";
+
+/// Geometry of the main window in physical pixels. `x`/`y`/`width`/`height` are the restored
+/// (non-maximized) bounds, so un-maximizing after a restore goes back to the saved size.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WindowState {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub maximized: bool,
+}
+
+/// A connected display, in physical pixels. `index` is what `window_move_to_monitor` takes; it is
+/// only stable until monitors are plugged in or out, so list again before moving.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f64,
+    pub primary: bool,
+}
+
+#[tauri::command]
+pub fn window_save_state(app: AppHandle) -> Result<(), String> {
+    let window = main_window(&app)?;
+    save_state(&app, &window)
+}
+
+#[tauri::command]
+pub fn window_restore_state(app: AppHandle) -> Result<Option<WindowState>, String> {
+    let window = main_window(&app)?;
+    restore_state(&app, &window)
+}
+
+#[tauri::command]
+pub fn window_list_monitors(app: AppHandle) -> Result<Vec<MonitorInfo>, String> {
+    let window = main_window(&app)?;
+    let primary = window.primary_monitor().map_err(|err| err.to_string())?;
+    let monitors = window.available_monitors().map_err(|err| err.to_string())?;
+
+    Ok(monitors
+        .iter()
+        .enumerate()
+        .map(|(index, monitor)| MonitorInfo {
+            index,
+            name: monitor.name().cloned(),
+            x: monitor.position().x,
+            y: monitor.position().y,
+            width: monitor.size().width,
+            height: monitor.size().height,
+            scale_factor: monitor.scale_factor(),
+            primary: primary
+                .as_ref()
+                .is_some_and(|primary| primary.position() == monitor.position()),
+        })
+        .collect())
+}
+
+/// Moves the main window onto the monitor at `index` from `window_list_monitors`. With `center`
+/// it is centered there, otherwise it keeps its offset from the monitor's top-left corner. The
+/// window is shrunk to fit if needed, and a maximized window stays maximized on the new monitor.
+#[tauri::command]
+pub fn window_move_to_monitor(
+    app: AppHandle,
+    index: usize,
+    center: Option<bool>,
+) -> Result<(), String> {
+    let window = main_window(&app)?;
+    let monitors = window.available_monitors().map_err(|err| err.to_string())?;
+    let target = monitors
+        .get(index)
+        .ok_or_else(|| format!("no monitor at index {index}, {} connected", monitors.len()))?;
+
+    let maximized = window.is_maximized().map_err(|err| err.to_string())?;
+    if maximized {
+        window.unmaximize().map_err(|err| err.to_string())?;
+    }
+
+    let position = window.outer_position().map_err(|err| err.to_string())?;
+    let size = window.inner_size().map_err(|err| err.to_string())?;
+    let origin = window
+        .current_monitor()
+        .map_err(|err| err.to_string())?
+        .map(|monitor| *monitor.position())
+        .unwrap_or_default();
+    let target_position = target.position();
+    let target_size = target.size();
+
+    let (x, y) = if center.unwrap_or(false) {
+        (
+            target_position.x + (i64::from(target_size.width) - i64::from(size.width)) as i32 / 2,
+            target_position.y + (i64::from(target_size.height) - i64::from(size.height)) as i32 / 2,
+        )
+    } else {
+        (
+            target_position.x + (position.x - origin.x),
+            target_position.y + (position.y - origin.y),
+        )
+    };
+    let state = clamp_to_monitor(
+        &WindowState {
+            x,
+            y,
+            width: size.width,
+            height: size.height,
+            maximized,
+        },
+        target,
+    );
+
+    window
+        .set_size(PhysicalSize::new(state.width, state.height))
+        .map_err(|err| err.to_string())?;
+    window
+        .set_position(PhysicalPosition::new(state.x, state.y))
+        .map_err(|err| err.to_string())?;
+    if maximized {
+        window.maximize().map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Restores the saved geometry and saves it again whenever the main window is closed.
+pub fn setup_window_state(app: &AppHandle) {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW) else {
+        return;
+    };
+
+    if let Err(err) = restore_state(app, &window) {
+        log::warn!("Failed to restore window state: {err}");
+    }
+
+    let app_handle = app.clone();
+    let tracked = window.clone();
+    window.on_window_event(move |event| {
+        if let WindowEvent::CloseRequested { .. } = event {
+            if let Err(err) = save_state(&app_handle, &tracked) {
+                log::warn!("Failed to save window state: {err}");
+            }
+        }
+    });
+}
+
+fn main_window(app: &AppHandle) -> Result<WebviewWindow, String> {
+    app.get_webview_window(MAIN_WINDOW)
+        .ok_or_else(|| "main window not found".to_string())
+}
+
+fn save_state(app: &AppHandle, window: &WebviewWindow) -> Result<(), String> {
+    // A minimized window reports bogus coordinates (e.g. -32000 on Windows); keep the last state.
+    if window.is_minimized().map_err(|err| err.to_string())? {
+        return Ok(());
+    }
+
+    let maximized = window.is_maximized().map_err(|err| err.to_string())?;
+    let state = if maximized {
+        // The maximized bounds are just the monitor; keep the bounds saved before maximizing.
+        let Some(previous) = load_state(app)? else {
+            return Ok(());
+        };
+        WindowState {
+            maximized: true,
+            ..previous
+        }
+    } else {
+        let position = window.outer_position().map_err(|err| err.to_string())?;
+        let size = window.inner_size().map_err(|err| err.to_string())?;
+        WindowState {
+            x: position.x,
+            y: position.y,
+            width: size.width,
+            height: size.height,
+            maximized: false,
+        }
+    };
+
+    let json = serde_json::to_string(&state).map_err(|err| err.to_string())?;
+    let store = app.store(STORE_PATH).map_err(|err| err.to_string())?;
+    store.set(STORE_KEY, json);
+    store.save().map_err(|err| err.to_string())
+}
+
+fn load_state(app: &AppHandle) -> Result<Option<WindowState>, String> {
+    let store = app.store(STORE_PATH).map_err(|err| err.to_string())?;
+    let Some(value) = store.get(STORE_KEY) else {
+        return Ok(None);
+    };
+    let json = value
+        .as_str()
+        .ok_or_else(|| "invalid window state format".to_string())?;
+    serde_json::from_str(json)
+        .map(Some)
+        .map_err(|err| err.to_string())
+}
+
+fn restore_state(app: &AppHandle, window: &WebviewWindow) -> Result<Option<WindowState>, String> {
+    let Some(saved) = load_state(app)? else {
+        return Ok(None);
+    };
+
+    let monitors = window.available_monitors().map_err(|err| err.to_string())?;
+    let monitor = monitors
+        .iter()
+        .find(|monitor| contains(monitor, saved.x, saved.y))
+        .cloned()
+        .or(window.primary_monitor().map_err(|err| err.to_string())?);
+    let state = match monitor {
+        Some(monitor) => clamp_to_monitor(&saved, &monitor),
+        None => saved,
+    };
+
+    window
+        .set_size(PhysicalSize::new(state.width, state.height))
+        .map_err(|err| err.to_string())?;
+    window
+        .set_position(PhysicalPosition::new(state.x, state.y))
+        .map_err(|err| err.to_string())?;
+    if state.maximized {
+        window.maximize().map_err(|err| err.to_string())?;
+    }
+
+    Ok(Some(state))
+}
+
+fn contains(monitor: &Monitor, x: i32, y: i32) -> bool {
+    let position = monitor.position();
+    let size = monitor.size();
+    x >= position.x
+        && y >= position.y
+        && i64::from(x) < i64::from(position.x) + i64::from(size.width)
+        && i64::from(y) < i64::from(position.y) + i64::from(size.height)
+}
+
+/// Shrinks the window to fit the monitor and moves it fully onto it, so a state saved on a
+/// monitor that is no longer connected doesn't open off-screen.
+fn clamp_to_monitor(state: &WindowState, monitor: &Monitor) -> WindowState {
+    let position = monitor.position();
+    let size = monitor.size();
+    let width = state.width.min(size.width);
+    let height = state.height.min(size.height);
+    let max_x = position.x + (size.width - width) as i32;
+    let max_y = position.y + (size.height - height) as i32;
+
+    WindowState {
+        x: state.x.clamp(position.x, max_x),
+        y: state.y.clamp(position.y, max_y),
+        width,
+        height,
+        maximized: state.maximized,
+    }
+}
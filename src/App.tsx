@@ -159,6 +159,13 @@ interface HttpResponse {
   body: string;
 }
 
+interface DeepLinkRoute {
+  route: string;
+  params: Record<string, string>;
+  fragment: string | null;
+  original?: string;
+}
+
 type ApplicationStatus = "new" | "reviewing" | "accepted" | "rejected";
 type ApplicationItem = {
   id: string;
@@ -633,25 +640,29 @@ function getContributorIds() {
   return ["1VC27ZvS", "G5M35WYk", "WuT9u35Z"];
 }
 
+const PAGE_IDS = [
+  "home",
+  "projects",
+  "news",
+  "explore",
+  "media",
+  "settings",
+  "settings-debug",
+  "profile",
+  "editor",
+  "analytics",
+  "calendar",
+  "admin",
+  "roles",
+  "members",
+  "player-applications",
+  "applications"
+];
+
+const DEEP_LINK_ROUTES = [...PAGE_IDS, "projects/:id", "editor/:docId", "profile/:userId"];
+
 function isPage(route: string) {
-  return [
-    "home",
-    "projects",
-    "news",
-    "explore",
-    "media",
-    "settings",
-    "settings-debug",
-    "profile",
-    "editor",
-    "analytics",
-    "calendar",
-    "admin",
-    "roles",
-    "members",
-    "player-applications",
-    "applications"
-  ].includes(route);
+  return PAGE_IDS.includes(route);
 }
 
 function normalizeModrinthFields(item: ProjectItem) {
@@ -1864,6 +1875,7 @@ export default function App() {
   const [applicationActionLoading, setApplicationActionLoading] = useState(false);
   const [applicationActionError, setApplicationActionError] = useState<string | null>(null);
   const [appVersion, setAppVersion] = useState<string>("Unbekannt");
+  const [invalidDeepLink, setInvalidDeepLink] = useState<string | null>(null);
   const [showTeamApplyPopup, setShowTeamApplyPopup] = useState(false);
   const teamApplyPopupShownRef = useRef(false);
   const [showClippy, setShowClippy] = useState(false);
@@ -3399,6 +3411,29 @@ export default function App() {
     })();
   }, [appSettings.discordPresenceEnabled]);
 
+  useEffect(() => {
+    let unlistenTray: (() => void) | null = null;
+
+    listen<string>("tray:action", (event) => {
+      if (event.payload === "toggle_presence") {
+        setAppSettings((prev) => ({
+          ...prev,
+          discordPresenceEnabled: !prev.discordPresenceEnabled
+        }));
+      }
+    })
+      .then((stop) => {
+        unlistenTray = stop;
+      })
+      .catch(() => undefined);
+
+    return () => {
+      if (unlistenTray) {
+        unlistenTray();
+      }
+    };
+  }, []);
+
   useEffect(() => {
     const controller = new AbortController();
 
@@ -3482,25 +3517,41 @@ export default function App() {
     return () => controller.abort();
   }, []);
 
+  useEffect(() => {
+    if (!invalidDeepLink) {
+      return;
+    }
+    showToast("Dieser Link ist ungültig oder veraltet.", "error");
+    setInvalidDeepLink(null);
+  }, [invalidDeepLink]);
+
   useEffect(() => {
     let unlistenNavigate: (() => void) | null = null;
 
-    invoke<string | null>("deeplink_get_current_route")
-      .then((route) => {
-        if (route && isPage(route)) {
-          setActivePage(route);
-        }
-      })
-      .catch(() => undefined);
+    invoke("deeplink_register_routes", { routes: DEEP_LINK_ROUTES }).catch(() => undefined);
+
+    const openDeepLink = (target: DeepLinkRoute) => {
+      if (target.route === "not-found") {
+        setInvalidDeepLink(target.original ?? target.route);
+      } else if (isPage(target.route)) {
+        setActivePage(target.route);
+      }
+    };
 
-    listen("app:navigate", (event) => {
-      const route = String(event.payload ?? "");
-      if (isPage(route)) {
-        setActivePage(route);
+    listen<DeepLinkRoute>("app:navigate", (event) => {
+      if (event.payload) {
+        openDeepLink(event.payload);
       }
     })
       .then((stop) => {
         unlistenNavigate = stop;
+        // Only take the launch route once the listener is attached, so no link falls between.
+        return invoke<DeepLinkRoute | null>("deeplink_consume_pending_route");
+      })
+      .then((target) => {
+        if (target) {
+          openDeepLink(target);
+        }
       })
       .catch(() => undefined);
 
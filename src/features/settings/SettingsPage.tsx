@@ -5,7 +5,15 @@
   toastEnabled: boolean;
 }
 
-import { openExternalUrl } from "../../shared/utils/external";
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { isRunningInTauri, openExternalUrl } from "../../shared/utils/external";
+
+interface KeyringStatus {
+  available: boolean;
+  backend: string;
+  error: string | null;
+}
 
 interface SettingsPageProps {
   settings: AppSettings;
@@ -79,6 +87,66 @@ export function SettingsPage({
   availableUpdateVersion,
   appVersion
 }: SettingsPageProps) {
+  const [keyringStatus, setKeyringStatus] = useState<KeyringStatus | null>(null);
+  const [deepLinkRegistered, setDeepLinkRegistered] = useState(true);
+  const [deepLinkRegisterError, setDeepLinkRegisterError] = useState<string | null>(null);
+
+  useEffect(() => {
+    let cancelled = false;
+
+    const checkKeyring = async () => {
+      if (!(await isRunningInTauri())) {
+        return;
+      }
+      try {
+        const status = await invoke<KeyringStatus>("diagnostics_check_keyring");
+        if (!cancelled) {
+          setKeyringStatus(status);
+        }
+      } catch {
+        // Diagnostics are best-effort; the page works without them.
+      }
+    };
+
+    void checkKeyring();
+    return () => {
+      cancelled = true;
+    };
+  }, []);
+
+  useEffect(() => {
+    let cancelled = false;
+
+    const checkDeepLink = async () => {
+      if (!(await isRunningInTauri())) {
+        return;
+      }
+      try {
+        const registered = await invoke<boolean>("deeplink_check_registration");
+        if (!cancelled) {
+          setDeepLinkRegistered(registered);
+        }
+      } catch {
+        // Best-effort like the keyring check.
+      }
+    };
+
+    void checkDeepLink();
+    return () => {
+      cancelled = true;
+    };
+  }, []);
+
+  const registerDeepLink = async () => {
+    try {
+      await invoke("deeplink_register");
+      setDeepLinkRegistered(await invoke<boolean>("deeplink_check_registration"));
+      setDeepLinkRegisterError(null);
+    } catch (error) {
+      setDeepLinkRegisterError(String(error));
+    }
+  };
+
   const toggleSetting = (key: keyof AppSettings) => {
     onUpdate((prev) => ({ ...prev, [key]: !prev[key] }));
   };
@@ -98,6 +166,39 @@ export function SettingsPage({
         <p className="mt-2 text-muted text-lg">Verwalte deine App-Präferenzen und Konfigurationen.</p>
       </header>
 
+      {keyringStatus && !keyringStatus.available && (
+        <div className="rounded-2xl border border-orange-500/30 bg-orange-500/10 p-5 text-sm text-orange-200">
+          <p className="font-semibold text-orange-300">Sicherer Speicher nicht verfügbar</p>
+          <p className="mt-1">
+            {keyringStatus.backend} ist nicht erreichbar. Anmeldedaten werden deshalb nur
+            verschlüsselt in den App-Daten gespeichert.
+          </p>
+          {keyringStatus.error && (
+            <p className="mt-2 text-xs text-orange-200/70">{keyringStatus.error}</p>
+          )}
+        </div>
+      )}
+
+      {!deepLinkRegistered && (
+        <div className="rounded-2xl border border-orange-500/30 bg-orange-500/10 p-5 text-sm text-orange-200">
+          <p className="font-semibold text-orange-300">Link-Handler nicht registriert</p>
+          <p className="mt-1">
+            vision://-Links öffnen diese App nicht. Eine Anmeldung im Browser kann deshalb nur
+            über die lokale Weiterleitung zur App zurückkehren.
+          </p>
+          <button
+            type="button"
+            onClick={() => void registerDeepLink()}
+            className="mt-3 rounded-xl border border-orange-500/30 bg-orange-500/10 px-4 py-2 text-xs font-semibold text-orange-200 transition-all hover:bg-orange-500/20"
+          >
+            Jetzt registrieren
+          </button>
+          {deepLinkRegisterError && (
+            <p className="mt-2 text-xs text-orange-200/70">{deepLinkRegisterError}</p>
+          )}
+        </div>
+      )}
+
       <div className="grid grid-cols-1 lg:grid-cols-2 gap-8">
         {/* System Section */}
         <section className="glass rounded-3xl overflow-hidden">